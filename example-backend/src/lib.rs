@@ -1,12 +1,11 @@
 //! https://github.com/triton-inference-server/backend/blob/main/README.md#triton-backend-api
 
 use futures::executor::block_on;
+use std::sync::OnceLock;
 use triton_rs::Backend;
 use triton_rs::Model;
 use triton_rs::ModelInstance;
 use triton_rs::RequestReleaseFlags;
-use triton_rs::Response;
-use triton_rs::ResponseFlags;
 
 #[derive(Debug, Default)]
 struct InstanceState(usize);
@@ -17,6 +16,22 @@ impl InstanceState {
     }
 }
 
+/// Sends the responses of decoupled models after model_instance_execute
+/// returned, the streams run as tasks on STREAM_THREADS workers
+fn stream_runtime() -> Result<&'static tokio::runtime::Runtime, triton_rs::Error> {
+    const STREAM_THREADS: usize = 4;
+    static RUNTIME: OnceLock<tokio::runtime::Runtime> = OnceLock::new();
+    if let Some(runtime) = RUNTIME.get() {
+        return Ok(runtime);
+    }
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(STREAM_THREADS)
+        .build()
+        .map_err(|err| format!("Failed to start the response stream runtime: {err}"))?;
+    // Another instance may have started one meanwhile, the first one is kept
+    Ok(RUNTIME.get_or_init(|| runtime))
+}
+
 #[derive(Debug)]
 struct SubModelExecutor(triton_rs::ModelExecutor);
 
@@ -118,16 +133,30 @@ impl Backend for ExampleBackend {
             println!("[EXAMPLE] {output1_name} as Array1<f32>: {output1:?}");
             let output1 = output1?.to_owned();
 
-            // let mut response = Response::from_request(request)?;
-            let factory = &triton_rs::ResponseFactory::from_request(request)?;
+            // one response for one-to-one models, a stream for decoupled ones
+            let responder = triton_rs::Responder::new(request)?;
+            if !responder.is_decoupled() {
+                let mut response = responder.response()?;
+                response.add_output_array("output", output1)?;
+                responder.send(response)?;
+                request.release(RequestReleaseFlags::ALL)?;
+                continue;
+            }
+            let runtime = stream_runtime()?;
+            let sender = responder.into_sender()?;
             // beyond here, we no longer need request
             request.release(RequestReleaseFlags::ALL)?;
 
-            let mut response = Response::from_factory(factory)?;
-            response.add_output_array("output", output1)?;
-            response.send(ResponseFlags::NONE, None)?;
-            // ... send more responses, then:
-            factory.send_flags(ResponseFlags::FINAL)?;
+            // responses are produced after model_instance_execute returns
+            runtime.spawn(async move {
+                let chunks = futures::stream::iter((1..=3).map(|step| {
+                    let output = output1.mapv(|x| x * step as f32);
+                    Ok(move |response: &mut triton_rs::Response| response.add_output_array("output", output))
+                }));
+                if let Err(err) = sender.stream_async(chunks).await {
+                    println!("[EXAMPLE] failed to send responses: {err}");
+                }
+            });
         }
 
         Ok(())
@@ -103,32 +103,56 @@ macro_rules! declare_backend {
         extern "C" fn TRITONBACKEND_ModelInitialize(
             model: *mut triton_rs::sys::TRITONBACKEND_Model,
         ) -> *const triton_rs::sys::TRITONSERVER_Error {
-            let mut model = triton_rs::ModelImpl::from_ptr(model);
-            triton_rs::call_checked!($class::model_initialize(model))
+            let data = triton_rs::ModelImpl::<<$class as triton_rs::Backend>::ModelState>::from_ptr(model);
+            if let Err(err) = data.initialize() {
+                return triton_rs::to_TRITONSERVER_Error(err);
+            }
+            // Triton does not finalize a model that failed to initialize
+            let initialized = $class::model_initialize(triton_rs::ModelImpl::from_ptr(model));
+            if initialized.is_err() {
+                let _ = data.finalize();
+            }
+            triton_rs::call_checked!(initialized)
         }
 
         #[no_mangle]
         extern "C" fn TRITONBACKEND_ModelFinalize(
             model: *mut triton_rs::sys::TRITONBACKEND_Model,
         ) -> *const triton_rs::sys::TRITONSERVER_Error {
-            let mut model = triton_rs::ModelImpl::from_ptr(model);
-            triton_rs::call_checked!($class::model_finalize(model))
+            let data = triton_rs::ModelImpl::<<$class as triton_rs::Backend>::ModelState>::from_ptr(model);
+            let finalized = $class::model_finalize(triton_rs::ModelImpl::from_ptr(model));
+            triton_rs::call_checked!(finalized.and(data.finalize()))
         }
 
         #[no_mangle]
         extern "C" fn TRITONBACKEND_ModelInstanceInitialize(
             instance: *mut triton_rs::sys::TRITONBACKEND_ModelInstance,
         ) -> *const triton_rs::sys::TRITONSERVER_Error {
-            let mut instance = triton_rs::ModelInstanceImpl::from_ptr(instance);
-            triton_rs::call_checked!($class::model_instance_initialize(instance))
+            let data = triton_rs::ModelInstanceImpl::<
+                <$class as triton_rs::Backend>::ModelInstanceState,
+                <$class as triton_rs::Backend>::ModelState,
+            >::from_ptr(instance);
+            if let Err(err) = data.initialize() {
+                return triton_rs::to_TRITONSERVER_Error(err);
+            }
+            // Triton does not finalize an instance that failed to initialize
+            let initialized = $class::model_instance_initialize(triton_rs::ModelInstanceImpl::from_ptr(instance));
+            if initialized.is_err() {
+                let _ = data.finalize();
+            }
+            triton_rs::call_checked!(initialized)
         }
 
         #[no_mangle]
         extern "C" fn TRITONBACKEND_ModelInstanceFinalize(
             instance: *mut triton_rs::sys::TRITONBACKEND_ModelInstance,
         ) -> *const triton_rs::sys::TRITONSERVER_Error {
-            let mut instance = triton_rs::ModelInstanceImpl::from_ptr(instance);
-            triton_rs::call_checked!($class::model_instance_finalize(instance))
+            let data = triton_rs::ModelInstanceImpl::<
+                <$class as triton_rs::Backend>::ModelInstanceState,
+                <$class as triton_rs::Backend>::ModelState,
+            >::from_ptr(instance);
+            let finalized = $class::model_instance_finalize(triton_rs::ModelInstanceImpl::from_ptr(instance));
+            triton_rs::call_checked!(finalized.and(data.finalize()))
         }
 
         #[no_mangle]
@@ -138,12 +162,22 @@ macro_rules! declare_backend {
             request_count: u32,
         ) -> *const triton_rs::sys::TRITONSERVER_Error {
                 let instance = triton_rs::ModelInstanceImpl::from_ptr(instance);
+                let host_policy = match instance.host_policy_name() {
+                    Ok(host_policy) => host_policy,
+                    Err(err) => return triton_rs::to_TRITONSERVER_Error(err),
+                };
+                let transaction_policy = match instance.model().and_then(|model| model.transaction_policy()) {
+                    Ok(transaction_policy) => transaction_policy,
+                    Err(err) => return triton_rs::to_TRITONSERVER_Error(err),
+                };
                 let requests = unsafe {
                     std::slice::from_raw_parts(requests, request_count as usize)
                 };
                 let requests = requests
                     .iter()
-                    .map(|req| triton_rs::Request::from_ptr(*req))
+                    .map(|req| triton_rs::Request::from_ptr(*req)
+                        .with_host_policy(host_policy.clone())
+                        .with_transaction_policy(transaction_policy))
                     .collect::<Vec<triton_rs::Request>>();
 
             triton_rs::call_checked!($class::model_instance_execute(instance, &requests))
@@ -0,0 +1,241 @@
+//! Dynamic batching helpers, like BackendInputCollector and BackendOutputResponder
+//! of the Triton backend utilities. The first dimension of every input and
+//! output is the batch dimension.
+
+use crate::{DataType, Error, Request, Response, ResponseFlags};
+use crate::data_type::{data_type_of, SupportedTypes};
+use crate::request::InputProperties;
+#[cfg(feature = "ndarray")]
+use ndarray::{ArrayBase, ArrayView, Data, Dimension, IxDyn};
+use std::ops::Range;
+
+/// One input of all requests, concatenated along the batch dimension
+pub struct Batch<T> {
+    pub data: Vec<T>,
+    /// Batched shape, the first dimension is the sum of all request batch sizes
+    pub shape: Vec<i64>,
+    /// Rows in `data` per request, None for requests that failed
+    rows: Vec<Option<Range<usize>>>,
+    errors: Vec<Option<Error>>,
+}
+
+impl<T: Copy + SupportedTypes> Batch<T> {
+    /// Gather input `name` of all requests. A request whose input is missing or
+    /// does not match the other requests fails on its own, see `send_errors`.
+    pub fn gather(requests: &[Request], name: &str) -> Result<Self, Error> {
+        let data_type = data_type_of::<T>()?;
+        let mut batch = Self { data: vec![], shape: vec![], rows: vec![], errors: vec![] };
+        let mut row_shape: Option<Vec<i64>> = None;
+        for request in requests {
+            let start = batch.batch_size();
+            match batch.append(request, name, data_type, &mut row_shape) {
+                Ok(batch_size) => {
+                    batch.rows.push(Some(start..start + batch_size));
+                    batch.errors.push(None);
+                },
+                Err(err) => {
+                    batch.rows.push(None);
+                    batch.errors.push(Some(err));
+                },
+            }
+        }
+        batch.shape = std::iter::once(batch.batch_size() as i64)
+            .chain(row_shape.unwrap_or_default())
+            .collect();
+        Ok(batch)
+    }
+
+    /// Append input `name` of `request`, returning its batch size
+    fn append(&mut self, request: &Request, name: &str, data_type: DataType,
+              row_shape: &mut Option<Vec<i64>>) -> Result<usize, Error> {
+        let shape = append_input(&mut self.data, request, name, data_type, |properties| {
+            let dims = batch_dims(name, properties)?;
+            match row_shape {
+                Some(row_shape) if row_shape != dims => {
+                    Err(format!("Input {name} shape does not match batch {row_shape:?} {properties:?}").into())
+                },
+                _ => Ok(()),
+            }
+        })?;
+        *row_shape = Some(shape[1..].to_vec());
+        Ok(shape[0] as usize)
+    }
+
+    /// Batched data as ndarray
+    #[cfg(feature = "ndarray")]
+    pub fn as_array(&self) -> Result<ArrayView<'_, T, IxDyn>, Error> {
+        let shape = crate::array_shape::<IxDyn>(&self.shape)?;
+        Ok(ArrayView::from_shape(shape, &self.data)?)
+    }
+
+    /// Split batched output into an output `name` on the response of each
+    /// request, skipping failed requests. `shape` includes the batch dimension.
+    /// A response that cannot take its output is sent with the error and taken.
+    pub fn scatter<U>(&self, responses: &mut [Option<Response>], name: &str, shape: &[i64], data: &[U])
+            -> Result<(), Error> where U: Copy + SupportedTypes {
+        self.check_responses(responses)?;
+        let element_count = crate::element_count(shape)?;
+        if shape.first() != Some(&(self.batch_size() as i64)) || data.len() != element_count {
+            return Err(format!("Output {name} of shape {shape:?} and {} elements does not match batch {:?}",
+                               data.len(), self.shape).into());
+        }
+        let row_len = crate::element_count(&shape[1..])?;
+        for (rows, response) in self.rows.iter().zip(responses.iter_mut()) {
+            let (Some(rows), Some(output)) = (rows, response.as_mut()) else {
+                continue;
+            };
+            let mut output_shape = shape.to_vec();
+            output_shape[0] = rows.len() as i64;
+            let data = &data[rows.start * row_len..rows.end * row_len];
+            if let Err(err) = output.add_output(name, &output_shape, data) {
+                response.take().unwrap().send(ResponseFlags::FINAL, Some(err))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `scatter`, from an array of any layout
+    #[cfg(feature = "ndarray")]
+    pub fn scatter_array<U, S, D>(&self, responses: &mut [Option<Response>], name: &str, array: ArrayBase<S, D>)
+            -> Result<(), Error> where U: Copy + SupportedTypes, S: Data<Elem = U>, D: Dimension {
+        let shape: Vec<i64> = array.shape().iter().map(|x| *x as i64).collect();
+        let array = array.as_standard_layout();
+        let data = array.as_slice().ok_or("Array not in standard layout")?;
+        self.scatter(responses, name, &shape, data)
+    }
+}
+
+impl<T> Batch<T> {
+    /// Sum of the batch sizes of all (successful) requests
+    pub fn batch_size(&self) -> usize {
+        self.rows.iter().rev().flatten().next().map_or(0, |rows| rows.end)
+    }
+
+    /// Rows of request `index` in the batch, None if it failed
+    pub fn rows(&self, index: usize) -> Option<Range<usize>> {
+        self.rows.get(index).cloned().flatten()
+    }
+
+    pub fn is_failed(&self, index: usize) -> bool {
+        self.rows.get(index).is_some_and(|rows| rows.is_none())
+    }
+
+    /// Send an error response for each request that failed to gather, taking
+    /// its response out of `responses`
+    pub fn send_errors(&mut self, responses: &mut [Option<Response>]) -> Result<(), Error> {
+        send_errors(&mut self.errors, responses)
+    }
+
+    fn check_responses(&self, responses: &[Option<Response>]) -> Result<(), Error> {
+        check_responses(self.rows.len(), responses)
+    }
+}
+
+/// Append input `name` of `request` to `data`, once `check` accepts it.
+/// Returns the shape of the input.
+pub(crate) fn append_input<T: Copy>(
+    data: &mut Vec<T>,
+    request: &Request,
+    name: &str,
+    data_type: DataType,
+    check: impl FnOnce(&InputProperties) -> Result<(), Error>,
+) -> Result<Vec<i64>, Error> {
+    let input = request.get_input(name)?;
+    let properties = input.properties()?;
+    if properties.datatype != data_type {
+        return Err(format!("DataType does not match {data_type} {properties:?}").into());
+    }
+    check(&properties)?;
+    let element_count = properties.element_count()?;
+    let bytes = input.contiguous_bytes(properties.buffer_count)?;
+    if bytes.len() != element_count * std::mem::size_of::<T>() {
+        return Err(format!("Expected {element_count} elements, got {} bytes {properties:?}",
+                           bytes.len()).into());
+    }
+    if data_type == DataType::BOOL && bytes.iter().any(|&b| b > 1) {
+        return Err(format!("Input {name} holds invalid BOOL values").into());
+    }
+
+    data.reserve(element_count);
+    unsafe {
+        // SAFETY: T is a plain numeric type of the checked size, bytes are
+        // copied unaligned into the reserved capacity
+        let end = data.as_mut_ptr().add(data.len()) as *mut u8;
+        std::ptr::copy_nonoverlapping(bytes.as_ptr(), end, bytes.len());
+        data.set_len(data.len() + element_count);
+    }
+    Ok(properties.shape)
+}
+
+/// Dimensions after the batch dimension
+pub(crate) fn batch_dims<'p>(name: &str, properties: &'p InputProperties) -> Result<&'p [i64], Error> {
+    match properties.shape.split_first() {
+        Some((&batch_size, dims)) if batch_size >= 0 => Ok(dims),
+        _ => Err(format!("Input {name} has no batch dimension {properties:?}").into()),
+    }
+}
+
+pub(crate) fn send_errors(errors: &mut [Option<Error>], responses: &mut [Option<Response>]) -> Result<(), Error> {
+    check_responses(errors.len(), responses)?;
+    for (error, response) in errors.iter_mut().zip(responses.iter_mut()) {
+        if let Some(error) = error.take() {
+            if let Some(response) = response.take() {
+                response.send(ResponseFlags::FINAL, Some(error))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn check_responses(requests: usize, responses: &[Option<Response>]) -> Result<(), Error> {
+    if responses.len() != requests {
+        return Err(format!("Expected {requests} responses, got {}", responses.len()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::{bytes, FakeRequest, FakeResponse};
+
+    #[test]
+    fn test_gather_scatter() {
+        let mut fakes = [
+            FakeRequest::with_input(DataType::FP32, &[1, 2], &[1.0f32, 2.0]),
+            FakeRequest::with_input(DataType::FP32, &[1, 3], &[0.0f32; 3]), // shape mismatch
+            FakeRequest::with_input(DataType::FP32, &[2, 2], &[3.0f32, 4.0, 5.0, 6.0]),
+            FakeRequest::with_input(DataType::INT32, &[1, 2], &[0.0f32; 2]),
+        ];
+        let requests: Vec<Request> = fakes.iter_mut().map(|fake| fake.as_request()).collect();
+        let mut batch = Batch::<f32>::gather(&requests, "fake").unwrap();
+        assert_eq!(vec![3, 2], batch.shape);
+        assert_eq!(vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0], batch.data);
+        assert_eq!(Some(0..1), batch.rows(0));
+        assert_eq!(Some(1..3), batch.rows(2));
+        assert!(batch.is_failed(1) && batch.is_failed(3));
+        assert!(Batch::<f32>::gather(&requests, "missing").unwrap().is_failed(0));
+
+        let mut fake_responses: Vec<FakeResponse> = (0..4).map(|_| FakeResponse::default()).collect();
+        let mut responses: Vec<Option<Response>> = fake_responses.iter_mut()
+            .map(|fake| Some(Response::from_ptr(fake.as_ptr())))
+            .collect();
+        batch.send_errors(&mut responses).unwrap();
+        assert!(responses[1].is_none() && responses[3].is_none());
+
+        let output: Vec<i64> = (0..6).collect();
+        assert!(batch.scatter(&mut responses, "out", &[2, 3], &output).is_err());
+        assert!(batch.scatter(&mut responses[..2], "out", &[3, 2], &output).is_err());
+        batch.scatter(&mut responses, "out", &[3, 2], &output).unwrap();
+        drop(responses);
+
+        assert_eq!(vec![1, 2], fake_responses[0].outputs[0].shape);
+        assert_eq!(bytes(&[0i64, 1]), fake_responses[0].outputs[0].data);
+        assert_eq!(vec![2, 2], fake_responses[2].outputs[0].shape);
+        assert_eq!(bytes(&[2i64, 3, 4, 5]), fake_responses[2].outputs[0].data);
+        let (flags, error) = fake_responses[1].sent.as_ref().unwrap();
+        assert_eq!(ResponseFlags::FINAL as u32, *flags);
+        assert!(error.as_ref().unwrap().contains("does not match batch"));
+        assert!(fake_responses[1].outputs.is_empty());
+    }
+}
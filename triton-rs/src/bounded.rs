@@ -0,0 +1,389 @@
+//! Bounded response queue for decoupled models. `TRITONBACKEND_ResponseSend`
+//! never blocks, so a generator outrunning a slow consumer would pile up
+//! responses. BoundedSender puts a bounded queue in front of the response
+//! stream: producers wait for capacity, a task on the caller's runtime fills
+//! and sends the responses in order.
+//!
+//! Triton does not tell backends when it released or delivered a response,
+//! so the bound only covers the responses queued here. Those handed to Triton
+//! are not bounded, there is no backpressure from the client.
+
+use crate::{CancellationToken, Error, IntoResponse, Metric, ResponseSender};
+use crate::cancel::cancelled_error;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::runtime::Handle;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+enum Message<C> {
+    Chunk(C),
+    Error(String),
+}
+
+/// Gauges shared by the BoundedSenders of a model, e.g. one per instance
+pub struct BoundedMetrics {
+    /// Responses queued in all senders, not those already handed to Triton
+    queued: Metric,
+    /// Highest `queued` seen
+    high_water_mark: Metric,
+    queued_count: AtomicUsize,
+    max_queued: AtomicUsize,
+    /// Publishes `max_queued` in order, so the gauge never goes down
+    publish: Mutex<()>,
+}
+
+impl BoundedMetrics {
+    pub fn new(queued: Metric, high_water_mark: Metric) -> Self {
+        Self {
+            queued,
+            high_water_mark,
+            queued_count: AtomicUsize::new(0),
+            max_queued: AtomicUsize::new(0),
+            publish: Mutex::new(()),
+        }
+    }
+
+    // Metrics are best effort, check_err already reports failures
+    fn enqueued(&self) {
+        let _ = self.queued.increment(1.0);
+        let queued = self.queued_count.fetch_add(1, Ordering::Relaxed) + 1;
+        if self.max_queued.fetch_max(queued, Ordering::Relaxed) < queued {
+            let _publish = self.publish.lock().unwrap_or_else(|err| err.into_inner());
+            let _ = self.high_water_mark.set(self.max_queued.load(Ordering::Relaxed) as f64);
+        }
+    }
+
+    fn dequeued(&self) {
+        self.queued_count.fetch_sub(1, Ordering::Relaxed);
+        let _ = self.queued.increment(-1.0);
+    }
+}
+
+/// Decoupled response stream with at most `capacity` responses queued.
+///
+/// Send chunks with `send` from async code or `blocking_send` from threads,
+/// then end the stream with `finish` or `send_error` (`blocking_finish` or
+/// `blocking_send_error` from threads). A failing chunk ends the stream with
+/// its error, later sends fail, as does cancellation of the request.
+/// Dropping the sender ends the stream with FINAL once the queue is drained.
+pub struct BoundedSender<C> {
+    channel: mpsc::Sender<Message<C>>,
+    worker: JoinHandle<Result<usize, String>>,
+    runtime: Handle,
+    high_water_mark: AtomicUsize,
+    metrics: Option<Arc<BoundedMetrics>>,
+    cancel: CancellationToken,
+}
+
+impl<C> BoundedSender<C> where C: IntoResponse + Send + 'static {
+    /// Queue in front of `sender`, drained by a task spawned on `runtime`
+    pub fn new(sender: ResponseSender, capacity: usize, runtime: &Handle) -> Result<Self, Error> {
+        Self::spawn(sender, capacity, runtime, None)
+    }
+
+    /// Also report queued responses and their high-water mark
+    pub fn with_metrics(
+        sender: ResponseSender,
+        capacity: usize,
+        runtime: &Handle,
+        metrics: Arc<BoundedMetrics>,
+    ) -> Result<Self, Error> {
+        Self::spawn(sender, capacity, runtime, Some(metrics))
+    }
+
+    fn spawn(
+        sender: ResponseSender,
+        capacity: usize,
+        runtime: &Handle,
+        metrics: Option<Arc<BoundedMetrics>>,
+    ) -> Result<Self, Error> {
+        if capacity == 0 {
+            return Err("BoundedSender capacity must be at least 1".into());
+        }
+        let cancel = sender.cancellation_token();
+        let (channel, receiver) = mpsc::channel(capacity);
+        let worker = runtime.spawn(drain(sender, receiver, metrics.clone()));
+        Ok(Self {
+            channel,
+            worker,
+            runtime: runtime.clone(),
+            high_water_mark: AtomicUsize::new(0),
+            metrics,
+            cancel,
+        })
+    }
+
+    /// Queue a response, waiting while `capacity` responses are queued
+    pub async fn send(&self, chunk: C) -> Result<(), Error> {
+        self.check_cancelled()?;
+        let permit = self.channel.reserve().await.map_err(|_| STREAM_ENDED)?;
+        self.enqueued();
+        permit.send(Message::Chunk(chunk));
+        Ok(())
+    }
+
+    /// Like `send`, for threads outside of any runtime. Fails on runtime
+    /// threads, async code should await `send` instead.
+    pub fn blocking_send(&self, chunk: C) -> Result<(), Error> {
+        check_blocking("blocking_send")?;
+        self.runtime.block_on(self.send(chunk))
+    }
+
+    /// Responses queued here, not yet handed to Triton. Those Triton has not
+    /// delivered yet are not counted.
+    pub fn queued(&self) -> usize {
+        self.channel.max_capacity() - self.channel.capacity()
+    }
+
+    /// Highest `queued` seen by this sender
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark.load(Ordering::Relaxed)
+    }
+
+    /// Whether the request was cancelled, producers can stop generating
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+
+    /// Send the queued responses, then end the stream with an error response
+    pub async fn send_error(self, error: Error) -> Result<usize, Error> {
+        // Unlike Error, the message can cross to the worker task
+        if let Ok(permit) = self.channel.reserve().await {
+            self.enqueued();
+            permit.send(Message::Error(error.to_string()));
+        }
+        self.finish().await
+    }
+
+    /// Send the queued responses and FINAL. Returns the number of responses
+    /// sent, like `ResponseSender::stream`.
+    pub async fn finish(self) -> Result<usize, Error> {
+        drop(self.channel);
+        match self.worker.await {
+            Ok(result) => Ok(result?),
+            Err(_) => Err("Response sender task panicked or was cancelled".into()),
+        }
+    }
+
+    /// Like `send_error`, blocks the thread. Fails on runtime threads, async
+    /// code should await `send_error` instead.
+    pub fn blocking_send_error(self, error: Error) -> Result<usize, Error> {
+        check_blocking("blocking_send_error")?;
+        let runtime = self.runtime.clone();
+        runtime.block_on(self.send_error(error))
+    }
+
+    /// Like `finish`, blocks the thread. Fails on runtime threads, async code
+    /// should await `finish` instead.
+    pub fn blocking_finish(self) -> Result<usize, Error> {
+        check_blocking("blocking_finish")?;
+        let runtime = self.runtime.clone();
+        runtime.block_on(self.finish())
+    }
+
+    fn check_cancelled(&self) -> Result<(), Error> {
+        match self.is_cancelled() {
+            true => Err(cancelled_error()),
+            false => Ok(()),
+        }
+    }
+
+    /// Count a response before it is queued, so the worker never sees it first
+    fn enqueued(&self) {
+        if let Some(metrics) = &self.metrics {
+            metrics.enqueued();
+        }
+        self.high_water_mark.fetch_max(self.queued(), Ordering::Relaxed);
+    }
+}
+
+const STREAM_ENDED: &str = "Response stream already ended";
+
+/// `Handle::block_on` panics when called from async code. Any thread of a
+/// runtime is refused, tokio does not tell which runtime or whether it is a
+/// blocking thread.
+fn check_blocking(method: &str) -> Result<(), Error> {
+    match Handle::try_current() {
+        Ok(_) => Err(format!("BoundedSender::{method} called on a runtime thread").into()),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Queued chunks as the items of `ResponseSender::stream_async`
+struct Queue<'a, C> {
+    receiver: &'a mut mpsc::Receiver<Message<C>>,
+    metrics: &'a Option<Arc<BoundedMetrics>>,
+}
+
+impl<C> Stream for Queue<'_, C> {
+    type Item = Result<C, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let message = std::task::ready!(self.receiver.poll_recv(cx));
+        if message.is_some() {
+            dequeued(self.metrics);
+        }
+        Poll::Ready(message.map(|message| match message {
+            Message::Chunk(chunk) => Ok(chunk),
+            Message::Error(error) => Err(error.into()),
+        }))
+    }
+}
+
+fn dequeued(metrics: &Option<Arc<BoundedMetrics>>) {
+    if let Some(metrics) = metrics {
+        metrics.dequeued();
+    }
+}
+
+async fn drain<C: IntoResponse>(
+    sender: ResponseSender,
+    mut receiver: mpsc::Receiver<Message<C>>,
+    metrics: Option<Arc<BoundedMetrics>>,
+) -> Result<usize, String> {
+    let queue = Queue { receiver: &mut receiver, metrics: &metrics };
+    let sent = sender.stream_async(queue).await.map_err(|err| err.to_string());
+
+    // The stream ended early, fail further sends and drop what is queued
+    receiver.close();
+    while receiver.try_recv().is_ok() {
+        dequeued(&metrics);
+    }
+    sent
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::FakeResponseFactory;
+    use crate::{MetricFamily, MetricKind, Response, ResponseFlags};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    type Chunk = Box<dyn FnOnce(&mut Response) -> Result<(), Error> + Send>;
+
+    fn token(token: i32) -> Chunk {
+        Box::new(move |response: &mut Response| response.add_output("token", &[1], &[token]))
+    }
+
+    fn runtime() -> tokio::runtime::Runtime {
+        tokio::runtime::Builder::new_multi_thread().worker_threads(1).build().unwrap()
+    }
+
+    #[test]
+    fn test_bounded_sender() {
+        let family = MetricFamily::new(MetricKind::Gauge, "queued", "Queued responses").unwrap();
+        let metrics = Arc::new(BoundedMetrics::new(
+            Metric::new(&family, &[]).unwrap(),
+            Metric::new(&family, &[("kind", "max")]).unwrap(),
+        ));
+        let runtime = runtime();
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let bounded = BoundedSender::<Chunk>::with_metrics(sender, 2, runtime.handle(), metrics.clone()).unwrap();
+
+        // Hold the worker in the first response, until the queue is full
+        let (started, wait_started) = channel();
+        let (release, wait_release) = channel::<()>();
+        bounded.blocking_send(Box::new(move |response: &mut Response| {
+            started.send(()).unwrap();
+            wait_release.recv().unwrap();
+            response.add_output("token", &[1], &[0i32])
+        })).unwrap();
+        wait_started.recv().unwrap();
+        assert_eq!(0, bounded.queued());
+        bounded.blocking_send(token(1)).unwrap();
+        bounded.blocking_send(token(2)).unwrap();
+        assert_eq!((2, 2), (bounded.queued(), bounded.high_water_mark()));
+
+        // The worker is held, time out on another runtime
+        let timer = tokio::runtime::Builder::new_current_thread().enable_time().build().unwrap();
+        let timeout = Duration::from_millis(20);
+        let full = timer.block_on(async { tokio::time::timeout(timeout, bounded.send(token(3))).await });
+        assert!(full.is_err());
+        assert_eq!((2, 2), (bounded.queued(), bounded.high_water_mark()));
+        assert_eq!((2.0, 2.0), (metrics.queued.value().unwrap(), metrics.high_water_mark.value().unwrap()));
+
+        release.send(()).unwrap();
+        runtime.block_on(bounded.send(token(3))).unwrap();
+        assert_eq!(4, runtime.block_on(bounded.finish()).unwrap());
+        assert_eq!(4, fake.responses.len());
+        assert_eq!(3i32.to_ne_bytes().to_vec(), fake.responses[3].outputs[0].data);
+        assert_eq!(vec![ResponseFlags::FINAL as u32], fake.flags);
+        assert_eq!(0.0, metrics.queued.value().unwrap());
+    }
+
+    #[test]
+    fn test_bounded_sender_error() {
+        let runtime = runtime();
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let bounded = BoundedSender::<Chunk>::new(sender, 1, runtime.handle()).unwrap();
+        bounded.blocking_send(token(0)).unwrap();
+        bounded.blocking_send(Box::new(|_: &mut Response| Err("out of memory".into()))).unwrap();
+        while bounded.blocking_send(token(1)).is_ok() {}
+        assert_eq!(1, bounded.blocking_finish().unwrap());
+        // The response the chunk failed to fill is deleted unsent
+        assert!(fake.responses[1].sent.is_none());
+        let sent = fake.responses[2].sent.clone().unwrap();
+        assert_eq!((ResponseFlags::FINAL as u32, Some("out of memory".to_string())), sent);
+        assert!(fake.flags.is_empty());
+
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let bounded = BoundedSender::<Chunk>::new(sender, 1, runtime.handle()).unwrap();
+        assert_eq!(0, bounded.blocking_send_error("cancelled".into()).unwrap());
+        assert_eq!(Some("cancelled".to_string()), fake.responses[0].sent.clone().unwrap().1);
+
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let bounded = BoundedSender::<Chunk>::new(sender, 1, runtime.handle()).unwrap();
+        let sent = runtime.block_on(async move {
+            bounded.send(token(0)).await?;
+            bounded.send_error("timeout".into()).await
+        });
+        assert_eq!(1, sent.unwrap());
+        assert_eq!(Some("timeout".to_string()), fake.responses[1].sent.clone().unwrap().1);
+
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        assert!(BoundedSender::<Chunk>::new(sender, 0, runtime.handle()).is_err());
+    }
+
+    #[test]
+    fn test_bounded_sender_cancelled() {
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let runtime = runtime();
+        let bounded = BoundedSender::<Chunk>::new(sender, 4, runtime.handle()).unwrap();
+        bounded.blocking_send(token(0)).unwrap();
+        bounded.cancellation_token().cancel();
+        assert!(bounded.is_cancelled());
+        assert!(bounded.blocking_send(token(1)).is_err());
+        assert!(bounded.blocking_finish().unwrap() <= 1);
+        let sent = fake.responses.last().unwrap().sent.clone().unwrap();
+        assert_eq!((ResponseFlags::FINAL as u32, Some("Request was cancelled".to_string())), sent);
+    }
+
+    #[test]
+    fn test_bounded_sender_async() {
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let runtime = runtime();
+        let bounded = BoundedSender::<Chunk>::new(sender, 4, runtime.handle()).unwrap();
+        let sent = runtime.block_on(async move {
+            assert!(bounded.blocking_send(token(0)).is_err());
+            bounded.send(token(1)).await?;
+            bounded.finish().await
+        });
+        assert_eq!(1, sent.unwrap());
+    }
+}
@@ -0,0 +1,160 @@
+//! Cancellation of requests whose client cancelled or disconnected, so that
+//! long generations stop early. Asking Triton needs r23.10 or newer, which
+//! the `cancellation` feature opts into; without it tokens are only cancelled
+//! by the backend itself, e.g. on shutdown.
+
+use crate::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+#[cfg(feature = "cancellation")]
+use std::sync::Mutex;
+#[cfg(feature = "cancellation")]
+use std::time::{Duration, Instant};
+
+/// Triton is asked at most this often, other polls only read a flag
+#[cfg(feature = "cancellation")]
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Cheap to poll and to clone cancellation flag of a response stream, see
+/// `ResponseFactory::cancellation_token`. Once cancelled, stays cancelled.
+///
+/// Without the `cancellation` feature the token never sees the client cancel
+/// or disconnect, it only reports `cancel()` calls of the backend.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    /// Factory to ask Triton, None after it was deleted
+    #[cfg(feature = "cancellation")]
+    factory: Mutex<Option<Poll>>,
+}
+
+#[cfg(feature = "cancellation")]
+#[derive(Debug)]
+struct Poll {
+    factory: *mut triton_sys::TRITONBACKEND_ResponseFactory,
+    last: Option<Instant>,
+}
+
+// SAFETY: the factory is only used under the mutex, and detached before it is deleted
+#[cfg(feature = "cancellation")]
+unsafe impl Send for Poll {}
+
+impl CancellationToken {
+    /// Token that only the backend cancels
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Token of `factory`, detached by its Drop
+    pub(crate) fn for_factory(factory: *mut triton_sys::TRITONBACKEND_ResponseFactory) -> Self {
+        #[cfg(feature = "cancellation")]
+        {
+            let inner = Inner {
+                cancelled: AtomicBool::new(false),
+                factory: Mutex::new(Some(Poll { factory, last: None })),
+            };
+            Self { inner: Arc::new(inner) }
+        }
+        #[cfg(not(feature = "cancellation"))]
+        {
+            let _ = factory;
+            Self::default()
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        if self.inner.cancelled.load(Ordering::Relaxed) {
+            return true;
+        }
+        #[cfg(feature = "cancellation")]
+        self.poll();
+        self.inner.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Ask Triton, unless another thread is asking or it was asked recently
+    #[cfg(feature = "cancellation")]
+    fn poll(&self) {
+        let Ok(mut poll) = self.inner.factory.try_lock() else { return };
+        let Some(poll) = poll.as_mut() else { return };
+        let now = Instant::now();
+        if poll.last.is_some_and(|last| now - last < POLL_INTERVAL) {
+            return;
+        }
+        poll.last = Some(now);
+        // An error is reported by check_err, keep going as if not cancelled
+        if factory_is_cancelled(poll.factory).unwrap_or(false) {
+            self.cancel();
+        }
+    }
+
+    /// Stop polling the factory, which is about to be deleted
+    pub(crate) fn detach(&self) {
+        #[cfg(feature = "cancellation")]
+        {
+            let mut poll = self.inner.factory.lock().unwrap_or_else(|err| err.into_inner());
+            *poll = None;
+        }
+    }
+}
+
+#[cfg(feature = "cancellation")]
+pub(crate) fn factory_is_cancelled(factory: *mut triton_sys::TRITONBACKEND_ResponseFactory) -> Result<bool, Error> {
+    let mut cancelled = false;
+    crate::check_err(unsafe {
+        triton_sys::TRITONBACKEND_ResponseFactoryIsCancelled(factory, &mut cancelled)
+    })?;
+    Ok(cancelled)
+}
+
+/// Error that ends a cancelled response stream
+pub(crate) fn cancelled_error() -> Error {
+    "Request was cancelled".into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancellation_token() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!token.is_cancelled());
+        clone.cancel();
+        assert!(token.is_cancelled() && clone.is_cancelled());
+    }
+
+    #[cfg(feature = "cancellation")]
+    #[test]
+    fn test_cancellation_token_polls_factory() {
+        use crate::stub::FakeResponseFactory;
+        use crate::ResponseFactory;
+
+        let mut fake = FakeResponseFactory::default();
+        let factory = ResponseFactory::from_ptr(fake.as_ptr());
+        let token = factory.cancellation_token();
+        assert!(!token.is_cancelled());
+        assert!(!factory.is_cancelled().unwrap());
+
+        fake.cancelled = true;
+        assert!(factory.is_cancelled().unwrap());
+        std::thread::sleep(POLL_INTERVAL);
+        assert!(token.is_cancelled());
+
+        // Detached by the factory, the token keeps its state
+        drop(factory);
+        assert!(fake.deleted && token.is_cancelled());
+        let token = ResponseFactory::from_ptr(fake.as_ptr()).cancellation_token();
+        fake.cancelled = true;
+        assert!(!token.is_cancelled());
+    }
+}
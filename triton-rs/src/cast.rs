@@ -0,0 +1,222 @@
+//! Element-wise conversion of tensor data between DataTypes
+
+use crate::{DataType, Error, SupportedTypes};
+
+/// Single tensor element, decoded from its DataType
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+}
+
+impl Value {
+    /// Decode one element of `data_type` from `bytes`, in native byte order
+    pub fn read(data_type: DataType, bytes: &[u8]) -> Result<Value, Error> {
+        let value = match data_type {
+            DataType::BOOL => Value::Bool(u8::from_ne_bytes(bytes.try_into()?) != 0),
+            DataType::UINT8 => Value::UInt(u8::from_ne_bytes(bytes.try_into()?).into()),
+            DataType::UINT16 => Value::UInt(u16::from_ne_bytes(bytes.try_into()?).into()),
+            DataType::UINT32 => Value::UInt(u32::from_ne_bytes(bytes.try_into()?).into()),
+            DataType::UINT64 => Value::UInt(u64::from_ne_bytes(bytes.try_into()?)),
+            DataType::INT8 => Value::Int(i8::from_ne_bytes(bytes.try_into()?).into()),
+            DataType::INT16 => Value::Int(i16::from_ne_bytes(bytes.try_into()?).into()),
+            DataType::INT32 => Value::Int(i32::from_ne_bytes(bytes.try_into()?).into()),
+            DataType::INT64 => Value::Int(i64::from_ne_bytes(bytes.try_into()?)),
+            #[cfg(feature = "half")]
+            DataType::FP16 => Value::Float(half::f16::from_ne_bytes(bytes.try_into()?).to_f64()),
+            #[cfg(feature = "half")]
+            DataType::BF16 => Value::Float(half::bf16::from_ne_bytes(bytes.try_into()?).to_f64()),
+            DataType::FP32 => Value::Float(f32::from_ne_bytes(bytes.try_into()?).into()),
+            DataType::FP64 => Value::Float(f64::from_ne_bytes(bytes.try_into()?)),
+            _ => return Err(format!("Cannot read {data_type} element").into()),
+        };
+        Ok(value)
+    }
+}
+
+/// Whether every value of `from` is exactly representable in `to`
+pub fn widens_to(from: DataType, to: DataType) -> bool {
+    use DataType::*;
+    from == to || match from {
+        UINT8 => matches!(to, UINT16 | UINT32 | UINT64 | INT16 | INT32 | INT64 | FP16 | BF16 | FP32 | FP64),
+        UINT16 => matches!(to, UINT32 | UINT64 | INT32 | INT64 | FP32 | FP64),
+        UINT32 => matches!(to, UINT64 | INT64 | FP64),
+        INT8 => matches!(to, INT16 | INT32 | INT64 | FP16 | BF16 | FP32 | FP64),
+        INT16 => matches!(to, INT32 | INT64 | FP32 | FP64),
+        INT32 => matches!(to, INT64 | FP64),
+        FP16 | BF16 => matches!(to, FP32 | FP64),
+        FP32 => matches!(to, FP64),
+        _ => false,
+    }
+}
+
+/// How to convert elements whose DataType differs from the requested type
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cast {
+    /// Fail on values out of range of the target type, or fractional values
+    /// for integer targets. Floats may be rounded to nearest.
+    Checked,
+    /// Clamp to the range of the target type, truncate fractions, NaN to 0
+    Saturating,
+}
+
+/// Element types that tensor data can be converted into
+pub trait CastElement: SupportedTypes + Copy {
+    /// Exact conversion, None when `value` is not representable
+    fn from_value(value: Value) -> Option<Self>;
+
+    /// Conversion according to `cast`, None when a checked cast fails
+    fn cast_from(value: Value, cast: Cast) -> Option<Self>;
+}
+
+impl CastElement for bool {
+    fn from_value(value: Value) -> Option<Self> {
+        match value {
+            Value::Bool(b) => Some(b),
+            Value::Int(0) | Value::UInt(0) => Some(false),
+            Value::Int(1) | Value::UInt(1) => Some(true),
+            Value::Float(0.0) => Some(false),
+            Value::Float(1.0) => Some(true),
+            _ => None,
+        }
+    }
+
+    fn cast_from(value: Value, cast: Cast) -> Option<Self> {
+        match (cast, value) {
+            (Cast::Checked, _) => Self::from_value(value),
+            (Cast::Saturating, Value::Bool(b)) => Some(b),
+            (Cast::Saturating, Value::Int(i)) => Some(i != 0),
+            (Cast::Saturating, Value::UInt(u)) => Some(u != 0),
+            (Cast::Saturating, Value::Float(f)) => Some(f != 0.0 && !f.is_nan()),
+        }
+    }
+}
+
+macro_rules! cast_element_int {
+    ($($t:ty),*) => {$(
+        impl CastElement for $t {
+            fn from_value(value: Value) -> Option<Self> {
+                match value {
+                    Value::Bool(b) => Some(b.into()),
+                    Value::Int(i) => <$t>::try_from(i).ok(),
+                    Value::UInt(u) => <$t>::try_from(u).ok(),
+                    Value::Float(f) => {
+                        // MAX + 1 is a power of two, exact in f64
+                        let (min, end) = (<$t>::MIN as f64, <$t>::MAX as f64 + 1.0);
+                        (f.fract() == 0.0 && f >= min && f < end).then_some(f as $t)
+                    },
+                }
+            }
+
+            fn cast_from(value: Value, cast: Cast) -> Option<Self> {
+                let clamp = |i: i128| i.clamp(<$t>::MIN as i128, <$t>::MAX as i128) as $t;
+                match (cast, value) {
+                    (Cast::Checked, _) => Self::from_value(value),
+                    (Cast::Saturating, Value::Bool(b)) => Some(b.into()),
+                    (Cast::Saturating, Value::Int(i)) => Some(clamp(i.into())),
+                    (Cast::Saturating, Value::UInt(u)) => Some(clamp(u.into())),
+                    // float to int `as` saturates, NaN becomes 0
+                    (Cast::Saturating, Value::Float(f)) => Some(f as $t),
+                }
+            }
+        }
+    )*};
+}
+
+cast_element_int!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+macro_rules! cast_element_float {
+    ($t:ty, $from_f64:expr, $to_f64:expr, $max:expr) => {
+        impl CastElement for $t {
+            fn from_value(value: Value) -> Option<Self> {
+                let exact = |f: f64| {
+                    let y: $t = $from_f64(f);
+                    ($to_f64(y) == f || f.is_nan()).then_some(y)
+                };
+                match value {
+                    Value::Bool(b) => exact(if b { 1.0 } else { 0.0 }),
+                    // round trip through i128 catches saturation at the i64/u64 limits
+                    Value::Int(i) => exact(i as f64).filter(|&y| $to_f64(y) as i128 == i as i128),
+                    Value::UInt(u) => exact(u as f64).filter(|&y| $to_f64(y) as i128 == u as i128),
+                    Value::Float(f) => exact(f),
+                }
+            }
+
+            fn cast_from(value: Value, cast: Cast) -> Option<Self> {
+                let f = match value {
+                    Value::Bool(b) => if b { 1.0 } else { 0.0 },
+                    Value::Int(i) => i as f64,
+                    Value::UInt(u) => u as f64,
+                    Value::Float(f) => f,
+                };
+                let y: $t = $from_f64(f);
+                if !$to_f64(y).is_infinite() || f.is_infinite() {
+                    return Some(y); // rounded to nearest
+                }
+                match cast {
+                    Cast::Checked => None,
+                    Cast::Saturating => Some(if f > 0.0 { $max } else { -$max }),
+                }
+            }
+        }
+    };
+}
+
+cast_element_float!(f32, |f: f64| f as f32, |y: f32| y as f64, f32::MAX);
+cast_element_float!(f64, |f: f64| f, |y: f64| y, f64::MAX);
+#[cfg(feature = "half")]
+cast_element_float!(half::f16, half::f16::from_f64, |y: half::f16| y.to_f64(), half::f16::MAX);
+#[cfg(feature = "half")]
+cast_element_float!(half::bf16, half::bf16::from_f64, |y: half::bf16| y.to_f64(), half::bf16::MAX);
+
+#[test]
+fn test_widens_to() {
+    assert!(widens_to(DataType::INT32, DataType::INT64));
+    assert!(widens_to(DataType::INT32, DataType::INT32));
+    assert!(widens_to(DataType::FP16, DataType::FP32));
+    assert!(!widens_to(DataType::INT64, DataType::INT32));
+    assert!(!widens_to(DataType::INT32, DataType::FP32));
+    assert!(!widens_to(DataType::UINT32, DataType::INT32));
+    assert!(!widens_to(DataType::BYTES, DataType::UINT8));
+}
+
+#[test]
+fn test_from_value() {
+    assert_eq!(Some(-3i64), i64::from_value(Value::Int(-3)));
+    assert_eq!(None, u8::from_value(Value::Int(-3)));
+    assert_eq!(None, i8::from_value(Value::UInt(200)));
+    assert_eq!(Some(7u32), u32::from_value(Value::Float(7.0)));
+    assert_eq!(None, u32::from_value(Value::Float(7.5)));
+    assert_eq!(None, i64::from_value(Value::Float(9.3e18)));
+    assert_eq!(None, u8::from_value(Value::Float(f64::NAN)));
+    assert_eq!(Some(0.5f32), f32::from_value(Value::Float(0.5)));
+    assert_eq!(None, f32::from_value(Value::Float(0.1)));
+    assert_eq!(None, f32::from_value(Value::Int(16_777_217)));
+    assert_eq!(None, f64::from_value(Value::Int(i64::MAX)));
+    assert_eq!(Some(true), bool::from_value(Value::UInt(1)));
+    assert_eq!(None, bool::from_value(Value::Int(2)));
+    assert!(f64::from_value(Value::Float(f64::NAN)).unwrap().is_nan());
+
+    assert_eq!(Some(-3i8), i8::cast_from(Value::Int(-3), Cast::Checked));
+    assert_eq!(None, i8::cast_from(Value::Int(300), Cast::Checked));
+    assert_eq!(Some(127i8), i8::cast_from(Value::Int(300), Cast::Saturating));
+    assert_eq!(Some(0u16), u16::cast_from(Value::Int(-5), Cast::Saturating));
+    assert_eq!(Some(u32::MAX), u32::cast_from(Value::UInt(u64::MAX), Cast::Saturating));
+    assert_eq!(None, i32::cast_from(Value::Float(2.5), Cast::Checked));
+    assert_eq!(Some(2i32), i32::cast_from(Value::Float(2.5), Cast::Saturating));
+    assert_eq!(Some(0i32), i32::cast_from(Value::Float(f64::NAN), Cast::Saturating));
+    assert_eq!(Some(0.1f32), f32::cast_from(Value::Float(0.1), Cast::Checked));
+    assert_eq!(None, f32::cast_from(Value::Float(1e300), Cast::Checked));
+    assert_eq!(Some(-f32::MAX), f32::cast_from(Value::Float(-1e300), Cast::Saturating));
+    assert_eq!(Some(f32::INFINITY), f32::cast_from(Value::Float(f64::INFINITY), Cast::Checked));
+    assert_eq!(Some(16_777_216f32), f32::cast_from(Value::Int(16_777_217), Cast::Checked));
+    assert_eq!(None, bool::cast_from(Value::Int(2), Cast::Checked));
+    assert_eq!(Some(true), bool::cast_from(Value::Int(2), Cast::Saturating));
+
+    let bytes = (-2i16).to_ne_bytes();
+    assert_eq!(Value::Int(-2), Value::read(DataType::INT16, &bytes).unwrap());
+    assert!(Value::read(DataType::INT32, &bytes).is_err());
+    assert!(Value::read(DataType::BYTES, &bytes).is_err());
+}
@@ -1,4 +1,4 @@
-use std::ffi::{CStr, CString};
+use crate::Error;
 
 #[derive(Debug,Clone,Copy,PartialEq)]
 #[repr(u32)]
@@ -21,8 +21,43 @@ pub enum DataType {
 }
 
 impl DataType {
-    pub fn byte_size(&self) -> u32 {
-        unsafe { triton_sys::TRITONSERVER_DataTypeByteSize(self.into()) }
+    /// Size of one element in bytes, 0 for BYTES (variable size) and INVALID.
+    /// Matches TRITONSERVER_DataTypeByteSize.
+    pub const fn byte_size(&self) -> u32 {
+        match self {
+            Self::BOOL | Self::UINT8 | Self::INT8 => 1,
+            Self::UINT16 | Self::INT16 | Self::FP16 | Self::BF16 => 2,
+            Self::UINT32 | Self::INT32 | Self::FP32 => 4,
+            Self::UINT64 | Self::INT64 | Self::FP64 => 8,
+            Self::BYTES | Self::INVALID => 0,
+        }
+    }
+
+    /// KServe protocol name, as TRITONSERVER_DataTypeString
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::INVALID => "<invalid>",
+            Self::BOOL => "BOOL",
+            Self::UINT8 => "UINT8",
+            Self::UINT16 => "UINT16",
+            Self::UINT32 => "UINT32",
+            Self::UINT64 => "UINT64",
+            Self::INT8 => "INT8",
+            Self::INT16 => "INT16",
+            Self::INT32 => "INT32",
+            Self::INT64 => "INT64",
+            Self::FP16 => "FP16",
+            Self::FP32 => "FP32",
+            Self::FP64 => "FP64",
+            Self::BYTES => "BYTES",
+            Self::BF16 => "BF16",
+        }
+    }
+
+    /// Like `From<&str>`: INVALID instead of an error for unknown names
+    #[deprecated(note = "use `str::parse`, which reports unknown names")]
+    pub fn from_name(data_type: &str) -> DataType {
+        data_type.parse().unwrap_or(DataType::INVALID)
     }
 }
 
@@ -48,42 +83,146 @@ impl From<u32> for DataType {
     }
 }
 
+/// Accepts KServe names (`FP32`, `BYTES`) and model config names (`TYPE_FP32`,
+/// `TYPE_STRING`)
+impl std::str::FromStr for DataType {
+    type Err = Error;
+
+    fn from_str(data_type: &str) -> Result<DataType, Error> {
+        let name = match data_type.strip_prefix("TYPE_") {
+            Some("STRING") => "BYTES",
+            Some(name) => name,
+            None => data_type,
+        };
+        let data_type = match name {
+            "BOOL" => Self::BOOL,
+            "UINT8" => Self::UINT8,
+            "UINT16" => Self::UINT16,
+            "UINT32" => Self::UINT32,
+            "UINT64" => Self::UINT64,
+            "INT8" => Self::INT8,
+            "INT16" => Self::INT16,
+            "INT32" => Self::INT32,
+            "INT64" => Self::INT64,
+            "FP16" => Self::FP16,
+            "FP32" => Self::FP32,
+            "FP64" => Self::FP64,
+            "BYTES" => Self::BYTES,
+            "BF16" => Self::BF16,
+            _ => return Err(format!("Unknown DataType {data_type:?}").into()),
+        };
+        Ok(data_type)
+    }
+}
+
+/// Deprecated, INVALID for unknown names like `DataType::from_name`. Use
+/// `str::parse`, which reports them. Rust cannot mark trait impls deprecated.
 impl From<&str> for DataType {
+    #[allow(deprecated)]
     fn from(data_type: &str) -> DataType {
-        let data_type = data_type.strip_prefix("TYPE_").unwrap_or(data_type);
-        let data_type = CString::new(data_type).expect("malformed DataType str");
-        let data_type = unsafe {
-            triton_sys::TRITONSERVER_StringToDataType(data_type.as_ptr())
-        };
-        data_type.into()
+        Self::from_name(data_type)
     }
 }
 
 impl From<&DataType> for u32 { fn from(v: &DataType) -> u32 { *v as u32 } }
 impl std::fmt::Display for DataType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let cstr = unsafe {
-            // The returned string is not owned by the caller and so should not be
-            // modified or freed.
-            let char_ptr = triton_sys::TRITONSERVER_DataTypeString(self.into());
-            CStr::from_ptr(char_ptr)
-        };
-        f.write_str(cstr.to_str().unwrap())
+        f.write_str(self.as_str())
     }
 }
 
 pub trait SupportedTypes : Clone { fn of() -> DataType { DataType::INVALID } }
 
 impl SupportedTypes for bool { fn of() -> DataType { DataType::BOOL } }
-//impl SupportedTypes for u8 { fn of() -> DataType { DataType::UINT8 } }
-// ...
+impl SupportedTypes for u8 { fn of() -> DataType { DataType::UINT8 } }
+impl SupportedTypes for u16 { fn of() -> DataType { DataType::UINT16 } }
 impl SupportedTypes for u32 { fn of() -> DataType { DataType::UINT32 } }
 impl SupportedTypes for u64 { fn of() -> DataType { DataType::UINT64 } }
+impl SupportedTypes for i8 { fn of() -> DataType { DataType::INT8 } }
+impl SupportedTypes for i16 { fn of() -> DataType { DataType::INT16 } }
 impl SupportedTypes for i32 { fn of() -> DataType { DataType::INT32 } }
 impl SupportedTypes for i64 { fn of() -> DataType { DataType::INT64 } }
 impl SupportedTypes for f32 { fn of() -> DataType { DataType::FP32 } }
 impl SupportedTypes for f64 { fn of() -> DataType { DataType::FP64 } }
-// ...
-//impl SupportedTypes for &str { fn of() -> DataType { DataType::BYTES } }
-impl SupportedTypes for u8 { fn of() -> DataType { DataType::BYTES } } // fixme
+#[cfg(feature = "half")]
+impl SupportedTypes for half::f16 { fn of() -> DataType { DataType::FP16 } }
+#[cfg(feature = "half")]
+impl SupportedTypes for half::bf16 { fn of() -> DataType { DataType::BF16 } }
+impl SupportedTypes for RawBytes { fn of() -> DataType { DataType::BYTES } }
+
+/// One byte of serialized BYTES tensor data, i.e. length-prefixed elements as
+/// produced by `encode_string`. Keeps BYTES apart from UINT8 (`u8`) tensors.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct RawBytes(pub u8);
+
+/// DataType of T, verified to match the size of T. BYTES elements have no fixed
+/// size, their data is handled as `RawBytes`.
+pub(crate) fn data_type_of<T: SupportedTypes>() -> Result<DataType, Error> {
+    let data_type = <T as SupportedTypes>::of();
+    let byte_size = match data_type {
+        DataType::BYTES => std::mem::size_of::<RawBytes>(),
+        DataType::INVALID => 0,
+        _ => data_type.byte_size() as usize,
+    };
+    if byte_size == 0 || byte_size != std::mem::size_of::<T>() {
+        return Err(format!("Element size {} does not match {data_type} ({byte_size})",
+                           std::mem::size_of::<T>()).into());
+    }
+    Ok(data_type)
+}
 
+#[test]
+fn test_data_type_of() {
+    assert_eq!(DataType::BOOL, data_type_of::<bool>().unwrap());
+    assert_eq!(DataType::UINT8, data_type_of::<u8>().unwrap());
+    assert_eq!(DataType::UINT16, data_type_of::<u16>().unwrap());
+    assert_eq!(DataType::UINT32, data_type_of::<u32>().unwrap());
+    assert_eq!(DataType::UINT64, data_type_of::<u64>().unwrap());
+    assert_eq!(DataType::INT8, data_type_of::<i8>().unwrap());
+    assert_eq!(DataType::INT16, data_type_of::<i16>().unwrap());
+    assert_eq!(DataType::INT32, data_type_of::<i32>().unwrap());
+    assert_eq!(DataType::INT64, data_type_of::<i64>().unwrap());
+    assert_eq!(DataType::FP32, data_type_of::<f32>().unwrap());
+    assert_eq!(DataType::FP64, data_type_of::<f64>().unwrap());
+    #[cfg(feature = "half")]
+    assert_eq!(DataType::FP16, data_type_of::<half::f16>().unwrap());
+    #[cfg(feature = "half")]
+    assert_eq!(DataType::BF16, data_type_of::<half::bf16>().unwrap());
+    assert_eq!(DataType::BYTES, data_type_of::<RawBytes>().unwrap());
+
+    #[derive(Clone)]
+    struct Wrong(#[allow(dead_code)] u16);
+    impl SupportedTypes for Wrong { fn of() -> DataType { DataType::FP32 } }
+    assert!(data_type_of::<Wrong>().is_err());
+}
+
+#[test]
+fn test_data_type_names() {
+    let all = [
+        DataType::BOOL, DataType::UINT8, DataType::UINT16, DataType::UINT32,
+        DataType::UINT64, DataType::INT8, DataType::INT16, DataType::INT32,
+        DataType::INT64, DataType::FP16, DataType::FP32, DataType::FP64,
+        DataType::BYTES, DataType::BF16,
+    ];
+    for data_type in all {
+        let name = data_type.to_string();
+        assert_eq!(data_type, name.parse().unwrap());
+        assert_eq!(data_type, format!("TYPE_{name}").parse::<DataType>().unwrap());
+        assert_eq!(data_type, DataType::from(data_type as u32));
+    }
+    assert_eq!(DataType::BYTES, "TYPE_STRING".parse().unwrap());
+    assert_eq!("FP32", DataType::FP32.to_string());
+    assert_eq!(4, DataType::FP32.byte_size());
+    assert_eq!(0, DataType::BYTES.byte_size());
+
+    assert!("STRING".parse::<DataType>().is_err());
+    assert!("TYPE_FP128".parse::<DataType>().is_err());
+    assert!("FP32\0".parse::<DataType>().is_err());
+    assert!("<invalid>".parse::<DataType>().is_err());
+    assert_eq!(DataType::INVALID, DataType::from(12345));
+    #[allow(deprecated)]
+    let names = (DataType::from_name("TYPE_FP32"), DataType::from_name("FP128"));
+    assert_eq!((DataType::FP32, DataType::INVALID), names);
+    assert_eq!((DataType::FP32, DataType::INVALID), (DataType::from("FP32"), "FP128".into()));
+}
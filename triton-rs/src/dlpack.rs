@@ -0,0 +1,285 @@
+//! DLPack interop. The structs mirror dlpack.h (v0.8 ABI), so tensors can be
+//! exchanged with other native libraries without going through Rust types.
+//! https://github.com/dmlc/dlpack/blob/main/include/dlpack/dlpack.h
+
+use crate::{DataType, Error, MemoryType};
+use std::borrow::Cow;
+use std::ffi::c_void;
+use std::marker::PhantomData;
+
+pub const DL_CPU: i32 = 1;
+pub const DL_CUDA: i32 = 2;
+pub const DL_CUDA_HOST: i32 = 3;
+
+pub const DL_INT: u8 = 0;
+pub const DL_UINT: u8 = 1;
+pub const DL_FLOAT: u8 = 2;
+pub const DL_BFLOAT: u8 = 4;
+pub const DL_BOOL: u8 = 6;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DLDevice {
+    pub device_type: i32,
+    pub device_id: i32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DLDataType {
+    pub code: u8,
+    pub bits: u8,
+    pub lanes: u16,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct DLTensor {
+    pub data: *mut c_void,
+    pub device: DLDevice,
+    pub ndim: i32,
+    pub dtype: DLDataType,
+    pub shape: *mut i64,
+    /// In elements, null for compact row major
+    pub strides: *mut i64,
+    pub byte_offset: u64,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct DLManagedTensor {
+    pub dl_tensor: DLTensor,
+    pub manager_ctx: *mut c_void,
+    pub deleter: Option<unsafe extern "C" fn(*mut DLManagedTensor)>,
+}
+
+impl DLDevice {
+    /// Device holding Triton memory of `memory_type`
+    pub fn new(memory_type: MemoryType, memory_type_id: i64) -> Self {
+        let device_type = match memory_type {
+            MemoryType::CPU => DL_CPU,
+            MemoryType::CPU_PINNED => DL_CUDA_HOST,
+            MemoryType::GPU => DL_CUDA,
+        };
+        Self { device_type, device_id: memory_type_id as i32 }
+    }
+
+    /// Whether the host can dereference memory on this device
+    pub fn is_cpu(&self) -> bool {
+        matches!(self.device_type, DL_CPU | DL_CUDA_HOST)
+    }
+}
+
+impl TryFrom<DataType> for DLDataType {
+    type Error = Error;
+
+    fn try_from(data_type: DataType) -> Result<DLDataType, Error> {
+        let code = match data_type {
+            DataType::BOOL => DL_BOOL,
+            DataType::UINT8 | DataType::UINT16 | DataType::UINT32 | DataType::UINT64 => DL_UINT,
+            DataType::INT8 | DataType::INT16 | DataType::INT32 | DataType::INT64 => DL_INT,
+            DataType::FP16 | DataType::FP32 | DataType::FP64 => DL_FLOAT,
+            DataType::BF16 => DL_BFLOAT,
+            DataType::BYTES | DataType::INVALID => {
+                return Err(format!("{data_type} has no DLPack data type").into())
+            },
+        };
+        Ok(DLDataType { code, bits: data_type.byte_size() as u8 * 8, lanes: 1 })
+    }
+}
+
+impl TryFrom<DLDataType> for DataType {
+    type Error = Error;
+
+    fn try_from(dtype: DLDataType) -> Result<DataType, Error> {
+        let data_type = match (dtype.code, dtype.bits, dtype.lanes) {
+            (DL_BOOL, 8, 1) => DataType::BOOL,
+            (DL_UINT, 8, 1) => DataType::UINT8,
+            (DL_UINT, 16, 1) => DataType::UINT16,
+            (DL_UINT, 32, 1) => DataType::UINT32,
+            (DL_UINT, 64, 1) => DataType::UINT64,
+            (DL_INT, 8, 1) => DataType::INT8,
+            (DL_INT, 16, 1) => DataType::INT16,
+            (DL_INT, 32, 1) => DataType::INT32,
+            (DL_INT, 64, 1) => DataType::INT64,
+            (DL_FLOAT, 16, 1) => DataType::FP16,
+            (DL_FLOAT, 32, 1) => DataType::FP32,
+            (DL_FLOAT, 64, 1) => DataType::FP64,
+            (DL_BFLOAT, 16, 1) => DataType::BF16,
+            _ => return Err(format!("Unsupported DLPack data type {dtype:?}").into()),
+        };
+        Ok(data_type)
+    }
+}
+
+/// Owned DLManagedTensor borrowing memory of a Triton tensor. Calls its
+/// deleter when dropped, unless handed off with `into_raw`.
+pub struct DLPackTensor<'a> {
+    managed: *mut DLManagedTensor,
+    _data: PhantomData<&'a [u8]>,
+}
+
+/// Keeps the shape alive for the DLTensor pointing into it
+struct ManagerContext {
+    shape: Vec<i64>,
+}
+
+unsafe extern "C" fn delete_managed_tensor(managed: *mut DLManagedTensor) {
+    let managed = unsafe { Box::from_raw(managed) };
+    drop(unsafe { Box::from_raw(managed.manager_ctx as *mut ManagerContext) });
+}
+
+impl DLPackTensor<'_> {
+    /// Compact row major tensor over `data`, which must stay valid for 'a
+    pub(crate) fn new(data: *const c_void, device: DLDevice, data_type: DataType, shape: &[i64])
+            -> Result<Self, Error> {
+        let dtype = data_type.try_into()?;
+        let mut context = Box::new(ManagerContext { shape: shape.to_vec() });
+        let dl_tensor = DLTensor {
+            data: data as *mut c_void,
+            device,
+            ndim: shape.len().try_into()?,
+            dtype,
+            shape: context.shape.as_mut_ptr(),
+            strides: std::ptr::null_mut(),
+            byte_offset: 0,
+        };
+        let managed = Box::new(DLManagedTensor {
+            dl_tensor,
+            manager_ctx: Box::into_raw(context) as *mut c_void,
+            deleter: Some(delete_managed_tensor),
+        });
+        Ok(Self { managed: Box::into_raw(managed), _data: PhantomData })
+    }
+
+    pub fn tensor(&self) -> &DLTensor {
+        unsafe { &(*self.managed).dl_tensor }
+    }
+
+    /// Pointer for consumers that only borrow the tensor
+    pub fn as_ptr(&self) -> *mut DLManagedTensor {
+        self.managed
+    }
+
+    /// Hand off ownership, the consumer is responsible for calling the deleter.
+    ///
+    /// # Safety
+    /// The data is only borrowed, the consumer must be done with it before the
+    /// Triton request (or response) it belongs to is released.
+    pub unsafe fn into_raw(self) -> *mut DLManagedTensor {
+        let managed = self.managed;
+        std::mem::forget(self);
+        managed
+    }
+}
+
+impl Drop for DLPackTensor<'_> {
+    fn drop(&mut self) {
+        unsafe { delete_managed_tensor(self.managed) };
+    }
+}
+
+/// DLTensor in host memory, with row major data
+pub(crate) struct HostTensor<'t> {
+    pub data_type: DataType,
+    pub shape: &'t [i64],
+    pub data: Cow<'t, [u8]>,
+}
+
+/// Read a DLTensor in host memory, only copying when it is strided
+///
+/// # Safety
+/// `tensor` must describe valid memory, for as long as the HostTensor lives.
+pub(crate) unsafe fn read_tensor(tensor: &DLTensor) -> Result<HostTensor<'_>, Error> {
+    if !tensor.device.is_cpu() {
+        return Err(format!("DLPack tensor is on {:?}, expected CPU", tensor.device).into());
+    }
+    let data_type = DataType::try_from(tensor.dtype)?;
+    let ndim = usize::try_from(tensor.ndim)?;
+    let shape = match ndim {
+        0 => &[],
+        _ => unsafe { std::slice::from_raw_parts(tensor.shape, ndim) },
+    };
+    let element_count = crate::element_count(shape)?;
+    let element_size = data_type.byte_size() as usize;
+    if element_count == 0 {
+        return Ok(HostTensor { data_type, shape, data: Cow::Borrowed(&[]) });
+    }
+    let data = unsafe { (tensor.data as *const u8).add(tensor.byte_offset as usize) };
+    let strides = match tensor.strides.is_null() || ndim == 0 {
+        true => None,
+        false => Some(unsafe { std::slice::from_raw_parts(tensor.strides, ndim) }),
+    };
+    if strides.is_none_or(|strides| strides == compact_strides(shape)) {
+        let bytes = unsafe { std::slice::from_raw_parts(data, element_count * element_size) };
+        return Ok(HostTensor { data_type, shape, data: Cow::Borrowed(bytes) });
+    }
+    let strides = strides.unwrap_or_default();
+
+    // walk the elements in row major order
+    let mut bytes = Vec::with_capacity(element_count * element_size);
+    let mut index = vec![0i64; ndim];
+    for _ in 0..element_count {
+        let offset: i64 = index.iter().zip(strides).map(|(i, s)| i * s).sum();
+        let element = unsafe { data.offset(offset as isize * element_size as isize) };
+        bytes.extend_from_slice(unsafe { std::slice::from_raw_parts(element, element_size) });
+        for dim in (0..ndim).rev() {
+            index[dim] += 1;
+            if index[dim] < shape[dim] {
+                break;
+            }
+            index[dim] = 0;
+        }
+    }
+    Ok(HostTensor { data_type, shape, data: Cow::Owned(bytes) })
+}
+
+fn compact_strides(shape: &[i64]) -> Vec<i64> {
+    let mut strides = vec![1; shape.len()];
+    for dim in (1..shape.len()).rev() {
+        strides[dim - 1] = strides[dim] * shape[dim];
+    }
+    strides
+}
+
+#[test]
+fn test_dlpack_data_type() {
+    for data_type in [DataType::BOOL, DataType::UINT16, DataType::INT64, DataType::FP16, DataType::BF16] {
+        let dtype = DLDataType::try_from(data_type).unwrap();
+        assert_eq!(data_type, DataType::try_from(dtype).unwrap());
+    }
+    assert_eq!(DLDataType { code: DL_FLOAT, bits: 32, lanes: 1 }, DataType::FP32.try_into().unwrap());
+    assert!(DLDataType::try_from(DataType::BYTES).is_err());
+    assert!(DataType::try_from(DLDataType { code: DL_FLOAT, bits: 32, lanes: 4 }).is_err());
+}
+
+#[test]
+fn test_read_tensor() {
+    let data: Vec<u16> = (0..6).collect();
+    let mut shape = [3i64, 2];
+    let mut transposed = [1i64, 3];
+    let mut tensor = DLTensor {
+        data: data.as_ptr() as *mut c_void,
+        device: DLDevice { device_type: DL_CPU, device_id: 0 },
+        ndim: 2,
+        dtype: DataType::UINT16.try_into().unwrap(),
+        shape: shape.as_mut_ptr(),
+        strides: transposed.as_mut_ptr(),
+        byte_offset: 0,
+    };
+    let host = unsafe { read_tensor(&tensor) }.unwrap();
+    assert_eq!(DataType::UINT16, host.data_type);
+    assert_eq!(&[3, 2], host.shape);
+    assert_eq!(crate::stub::bytes(&[0u16, 3, 1, 4, 2, 5]), *host.data);
+
+    let mut compact = [2i64, 1];
+    tensor.strides = compact.as_mut_ptr();
+    tensor.byte_offset = 2;
+    unsafe { *tensor.shape = 2 };
+    let bytes = unsafe { read_tensor(&tensor) }.unwrap().data;
+    assert!(matches!(bytes, Cow::Borrowed(_)));
+    assert_eq!(crate::stub::bytes(&data[1..5]), *bytes);
+
+    tensor.device.device_type = DL_CUDA;
+    assert!(unsafe { read_tensor(&tensor) }.is_err());
+}
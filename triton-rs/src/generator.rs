@@ -0,0 +1,175 @@
+//! Streaming generators for decoupled models, e.g. one response per token:
+//! the backend produces the outputs of each response, `ResponseSender::stream`
+//! (or `stream_async` for a `Stream`) sends them and ends the stream with
+//! FINAL or the first error.
+
+use crate::{Error, Response, ResponseFlags, ResponseSender};
+use crate::cancel::cancelled_error;
+use futures_core::Stream;
+use std::future::poll_fn;
+use std::pin::pin;
+
+/// Outputs (and parameters) of one response of a stream
+pub trait IntoResponse {
+    fn into_response(self, response: &mut Response) -> Result<(), Error>;
+}
+
+impl<F> IntoResponse for F where F: FnOnce(&mut Response) -> Result<(), Error> {
+    fn into_response(self, response: &mut Response) -> Result<(), Error> {
+        self(response)
+    }
+}
+
+impl ResponseSender {
+    /// Send a response for each item of `generator`, then FINAL. An error of
+    /// the generator, or of filling a response, is sent to the client instead
+    /// and ends the stream, as does cancellation before the next item. Returns
+    /// the number of responses sent; fails only if Triton does not take a
+    /// response, e.g. because the client is gone.
+    pub fn stream<I, C>(self, generator: I) -> Result<usize, Error>
+    where I: IntoIterator<Item = Result<C, Error>>, C: IntoResponse {
+        let mut sent = 0;
+        let mut generator = generator.into_iter();
+        loop {
+            if self.is_cancelled() {
+                self.send_error(cancelled_error())?;
+                return Ok(sent);
+            }
+            let Some(item) = generator.next() else { break };
+            if !self.send_item(item)? {
+                return Ok(sent);
+            }
+            sent += 1;
+        }
+        self.finish()?;
+        Ok(sent)
+    }
+
+    /// Like `stream`, for a `Stream` of items from async code. Cancellation
+    /// is also checked once an item arrived, which can take long.
+    pub async fn stream_async<S, C>(self, generator: S) -> Result<usize, Error>
+    where S: Stream<Item = Result<C, Error>>, C: IntoResponse {
+        let mut sent = 0;
+        let mut generator = pin!(generator);
+        loop {
+            if self.is_cancelled() {
+                self.send_error(cancelled_error())?;
+                return Ok(sent);
+            }
+            let Some(item) = poll_fn(|cx| generator.as_mut().poll_next(cx)).await else { break };
+            if self.is_cancelled() {
+                self.send_error(cancelled_error())?;
+                return Ok(sent);
+            }
+            if !self.send_item(item)? {
+                return Ok(sent);
+            }
+            sent += 1;
+        }
+        self.finish()?;
+        Ok(sent)
+    }
+
+    /// Send the response of `item`, or its error with FINAL and return false
+    fn send_item<C: IntoResponse>(&self, item: Result<C, Error>) -> Result<bool, Error> {
+        let response = item.and_then(|chunk| {
+            let mut response = self.response()?;
+            chunk.into_response(&mut response)?;
+            Ok(response)
+        });
+        match response {
+            Ok(response) => self.send(response).map(|_| true),
+            Err(err) => self.response()?.send(ResponseFlags::FINAL, Some(err)).map(|_| false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::FakeResponseFactory;
+    use crate::ResponseFlags;
+
+    #[test]
+    fn test_stream() {
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let tokens = (0..3).map(|token: i32| {
+            Ok(move |response: &mut Response| response.add_output("token", &[1], &[token]))
+        });
+        assert_eq!(3, sender.stream(tokens).unwrap());
+        assert_eq!(3, fake.responses.len());
+        assert_eq!(2i32.to_ne_bytes().to_vec(), fake.responses[2].outputs[0].data);
+        assert!(fake.responses.iter().all(|response| response.sent == Some((ResponseFlags::NONE as u32, None))));
+        assert_eq!(vec![ResponseFlags::FINAL as u32], fake.flags);
+
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        type Chunk = fn(&mut Response) -> Result<(), Error>;
+        let chunks: Vec<Result<Chunk, Error>> = vec![
+            Ok(|response| response.set_parameter("index", 0)),
+            Err("out of memory".into()),
+            Ok(|_| unreachable!()),
+        ];
+        assert_eq!(1, sender.stream(chunks).unwrap());
+        let sent = fake.responses[1].sent.clone().unwrap();
+        assert_eq!((ResponseFlags::FINAL as u32, Some("out of memory".to_string())), sent);
+        assert!(fake.flags.is_empty() && fake.deleted);
+    }
+
+    #[test]
+    fn test_stream_cancelled() {
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let token = sender.cancellation_token();
+        let tokens = (0..).map(move |token_id: i32| {
+            if token_id == 1 {
+                token.cancel();
+            }
+            Ok(move |response: &mut Response| response.add_output("token", &[1], &[token_id]))
+        });
+        assert_eq!(2, sender.stream(tokens).unwrap());
+        let sent = fake.responses[2].sent.clone().unwrap();
+        assert_eq!((ResponseFlags::FINAL as u32, Some("Request was cancelled".to_string())), sent);
+    }
+
+    #[test]
+    fn test_stream_async() {
+        use futures::stream::{self, StreamExt};
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let tokens = stream::iter(0..3).then(|token: i32| async move {
+            tokio::task::yield_now().await;
+            Ok(move |response: &mut Response| response.add_output("token", &[1], &[token]))
+        });
+        assert_eq!(3, runtime.block_on(sender.stream_async(tokens)).unwrap());
+        assert_eq!(2i32.to_ne_bytes().to_vec(), fake.responses[2].outputs[0].data);
+        assert_eq!(vec![ResponseFlags::FINAL as u32], fake.flags);
+
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let chunks = stream::iter([Err::<fn(&mut Response) -> Result<(), Error>, _>("out of memory".into())]);
+        assert_eq!(0, runtime.block_on(sender.stream_async(chunks)).unwrap());
+        assert_eq!(Some("out of memory".to_string()), fake.responses[0].sent.clone().unwrap().1);
+
+        // Cancelled while waiting for the item, which is dropped
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        let token = sender.cancellation_token();
+        let tokens = stream::iter(0..).then(move |token_id: i32| {
+            let token = token.clone();
+            async move {
+                if token_id == 1 {
+                    token.cancel();
+                }
+                Ok(move |response: &mut Response| response.add_output("token", &[1], &[token_id]))
+            }
+        });
+        assert_eq!(1, runtime.block_on(sender.stream_async(tokens)).unwrap());
+        assert_eq!(2, fake.responses.len());
+        let sent = fake.responses[1].sent.clone().unwrap();
+        assert_eq!((ResponseFlags::FINAL as u32, Some("Request was cancelled".to_string())), sent);
+    }
+}
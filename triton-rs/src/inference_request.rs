@@ -1,11 +1,11 @@
-use crate::{check_err, DataType, Error, ModelExecutor};
+use crate::{check_err, CorrelationId, DataType, Error, ModelExecutor};
 use std::ffi::CString;
 
-#[cfg(feature = "ndarray")] use ndarray::{Array, IxDyn};
+#[cfg(feature = "ndarray")] use ndarray::{Array, Dimension};
 
 pub struct InferenceRequest {
     ptr: *mut triton_sys::TRITONSERVER_InferenceRequest,
-    #[cfg(feature = "ndarray")]
+    #[cfg_attr(not(any(feature = "ndarray", feature = "dlpack")), allow(dead_code))] // keeps input data alive
     datas: Vec<Vec<u8>>,
 }
 
@@ -79,26 +79,48 @@ impl InferenceRequest {
         Ok(())
     }
 
+    /// Add input from an array of any rank and memory layout, sent in standard
+    /// (row major) order. Arrays in standard layout are moved, not copied.
     #[cfg(feature = "ndarray")]
-    pub fn add_input_array<T>(&mut self, name: &str, array: Array<T, IxDyn>,
+    pub fn add_input_array<T, D>(&mut self, name: &str, array: Array<T, D>,
     ) -> Result<(), Box<dyn std::error::Error>>
-    where T: Copy + crate::data_type::SupportedTypes {
+    where T: Copy + crate::data_type::SupportedTypes, D: Dimension {
         let shape: Vec<i64> = array.shape().iter().map(|x| *x as i64).collect();
-        let data_type = <T as crate::data_type::SupportedTypes>::of();
-        assert_eq!(data_type.byte_size() as usize, std::mem::size_of::<T>());
+        let data_type = crate::data_type::data_type_of::<T>()?;
         self.add_input(name, data_type, &shape)?;
-        let is_empty = array.is_empty();
-        let (vec, Some(offset)) = array.into_raw_vec_and_offset() else {
-            assert!(is_empty);
+        let len = array.len();
+        if len == 0 {
             return Ok(()); // no data needs be sent
+        }
+        let vec = if array.is_standard_layout() {
+            let (vec, offset) = array.into_raw_vec_and_offset();
+            let offset = offset.unwrap_or(0);
+            if offset == 0 && vec.len() == len { vec } else { vec[offset..offset + len].to_vec() }
+        } else {
+            array.iter().copied().collect()
         };
-        assert_eq!(offset, 0);
         let vec: Vec<u8> = get_raw_bytes(vec);
         self.datas.push(vec);
         let slice: &[u8] = self.datas.last().unwrap();
         self.set_input_data(name, slice)
     }
 
+    /// Add input copied from a DLPack tensor in host memory, in row major order
+    ///
+    /// # Safety
+    /// `tensor` must describe valid memory
+    #[cfg(feature = "dlpack")]
+    pub unsafe fn add_input_dlpack(&mut self, name: &str, tensor: &crate::dlpack::DLTensor) -> Result<(), Error> {
+        let host = unsafe { crate::dlpack::read_tensor(tensor)? };
+        self.add_input(name, host.data_type, host.shape)?;
+        if host.data.is_empty() {
+            return Ok(()); // no data needs be sent
+        }
+        self.datas.push(host.data.into_owned());
+        let slice: &[u8] = self.datas.last().unwrap();
+        self.set_input_data(name, slice)
+    }
+
     pub fn set_request_id(&self, id: &str) -> Result<(), Error> {
         let cstr_id = CString::new(id)?;
         check_err(unsafe {
@@ -107,10 +129,19 @@ impl InferenceRequest {
         Ok(())
     }
 
-    pub fn set_correlation_id(&self, id: u64) -> Result<(), Error>{
-        check_err(unsafe {
-            triton_sys::TRITONSERVER_InferenceRequestSetCorrelationId(self.ptr, id)
-        })?;
+    /// Set the correlation ID, e.g. forwarded from `Request::get_correlation_id`
+    pub fn set_correlation_id(&self, id: impl Into<CorrelationId>) -> Result<(), Error>{
+        match id.into() {
+            CorrelationId::U64(id) => check_err(unsafe {
+                triton_sys::TRITONSERVER_InferenceRequestSetCorrelationId(self.ptr, id)
+            })?,
+            CorrelationId::String(id) => {
+                let cstr_id = CString::new(id)?;
+                check_err(unsafe {
+                    triton_sys::TRITONSERVER_InferenceRequestSetCorrelationIdString(self.ptr, cstr_id.as_ptr())
+                })?
+            },
+        }
         Ok(())
     }
 
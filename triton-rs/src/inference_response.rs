@@ -1,14 +1,16 @@
-use crate::{DataType, Error};
+use crate::{DataType, Error, ParameterValue};
 use std::ffi::CStr;
 use std::os::raw::{c_char, c_void};
 use std::collections::HashMap;
 
-#[cfg(feature = "ndarray")] use ndarray::{ArrayView, IntoDimension, IxDyn};
+#[cfg(feature = "ndarray")] use ndarray::{ArrayView, Dimension, IxDyn};
 
 mod detail {
 
-  use crate::check_err;
+  use crate::{check_err, ParameterValue};
   use super::OutputData;
+  use std::ffi::CStr;
+  use std::os::raw::{c_char, c_void};
 
   pub (crate) struct InferenceResponse {
     ptr: *mut triton_sys::TRITONSERVER_InferenceResponse,
@@ -30,12 +32,49 @@ mod detail {
     pub fn get_output_data(&self, out_idx: u32) -> Result<OutputData, Box<dyn std::error::Error>> {
         OutputData::get_output_data(self.ptr, out_idx)
     }
+
+    pub fn get_parameter_count(&self) -> Result<u32, Box<dyn std::error::Error>> {
+        let mut count = 0;
+        check_err(unsafe {
+            triton_sys::TRITONSERVER_InferenceResponseParameterCount(self.ptr, &mut count)
+        })?;
+        Ok(count)
+    }
+
+    /// Name and value of parameter `index`, None for BYTES parameters, whose
+    /// size is not exposed
+    pub fn get_parameter(&self, index: u32)
+            -> Result<Option<(String, ParameterValue)>, Box<dyn std::error::Error>> {
+        let mut name_ptr: *const c_char = std::ptr::null();
+        let mut parameter_type = triton_sys::TRITONSERVER_parametertype_enum_TRITONSERVER_PARAMETER_STRING;
+        let mut value_ptr: *const c_void = std::ptr::null();
+        check_err(unsafe {
+            triton_sys::TRITONSERVER_InferenceResponseParameter(
+                self.ptr, index, &mut name_ptr, &mut parameter_type, &mut value_ptr)
+        })?;
+        let name = unsafe { CStr::from_ptr(name_ptr) }.to_string_lossy().into_owned();
+        let value = match parameter_type {
+            triton_sys::TRITONSERVER_parametertype_enum_TRITONSERVER_PARAMETER_STRING => {
+                let value = unsafe { CStr::from_ptr(value_ptr as *const c_char) };
+                ParameterValue::String(value.to_string_lossy().into_owned())
+            },
+            triton_sys::TRITONSERVER_parametertype_enum_TRITONSERVER_PARAMETER_INT => {
+                ParameterValue::Int(unsafe { *(value_ptr as *const i64) })
+            },
+            triton_sys::TRITONSERVER_parametertype_enum_TRITONSERVER_PARAMETER_BOOL => {
+                ParameterValue::Bool(unsafe { *(value_ptr as *const bool) })
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some((name, value)))
+    }
   }
 
 } // detail
 
 pub struct InferenceResponse {
     outputs: HashMap<String, OutputData>,
+    parameters: Vec<(String, ParameterValue)>,
 }
 
 impl InferenceResponse {
@@ -47,7 +86,11 @@ impl InferenceResponse {
             let data = helper.get_output_data(index)?;
             outputs.insert(data.name.clone(), data);
         }
-        Ok(Self { outputs })
+        let mut parameters = vec![];
+        for index in 0..helper.get_parameter_count()? {
+            parameters.extend(helper.get_parameter(index)?);
+        }
+        Ok(Self { outputs, parameters })
     }
 
     pub fn get_output_count(&self) -> usize {
@@ -61,6 +104,15 @@ impl InferenceResponse {
     pub fn iter(&self) -> impl Iterator<Item = &OutputData> {
         self.outputs.values()
     }
+
+    /// Parameters set by the model, in order, except BYTES parameters
+    pub fn parameters(&self) -> &[(String, ParameterValue)] {
+        &self.parameters
+    }
+
+    pub fn get_parameter(&self, name: &str) -> Option<&ParameterValue> {
+        self.parameters.iter().find(|(key, _)| key == name).map(|(_, value)| value)
+    }
 }
 
 pub struct OutputData {
@@ -72,7 +124,7 @@ pub struct OutputData {
     pub memory_type_id: i64,
 }
 
-impl<'a> OutputData {
+impl OutputData {
     pub fn get_output_data(
         response: *mut triton_sys::TRITONSERVER_InferenceResponse,
         out_idx: u32,
@@ -124,19 +176,31 @@ impl<'a> OutputData {
         }
     }
 
+    /// Output data as ndarray of any rank, which must be N
     #[cfg(feature="ndarray")]
-    pub fn as_array<T, const N: usize>(&'a self)
-            -> Result<ArrayView<'a, T, IxDyn>, Error>
+    pub fn as_array<T, const N: usize>(&self)
+            -> Result<ArrayView<'_, T, IxDyn>, Error>
             where T: crate::data_type::SupportedTypes {
-        assert!(<T as crate::data_type::SupportedTypes>::of() == self.data_type);
-        assert!(N == self.shape.len());
-        const { assert!( N < 6) };
-        let shape: Vec<usize> = self.shape.iter().map(|&x| x as usize).collect();
-        let shape = shape.into_dimension();
-        let data = byte_slice_to::<T>(&self.data);
-        let array: ArrayView<T, IxDyn> = ArrayView::from_shape(shape, data)?;
+        let array = self.as_array_dim::<T, IxDyn>()?;
+        if array.ndim() != N {
+            return Err(format!("Expected {N} dimensions, got shape {:?}", array.shape()).into());
+        }
         Ok(array)
     }
+
+    /// Output data as ndarray of fixed dimension, e.g. `Ix2` for `ArrayView2`
+    #[cfg(feature="ndarray")]
+    pub fn as_array_dim<T, D>(&self) -> Result<ArrayView<'_, T, D>, Error>
+            where T: crate::data_type::SupportedTypes, D: Dimension {
+        let data_type = crate::data_type::data_type_of::<T>()?;
+        if data_type != self.data_type {
+            return Err(format!("DataType does not match {data_type} {self:?}").into());
+        }
+        let shape = crate::array_shape::<D>(&self.shape)?;
+        let data = crate::reinterpret::<T>(&self.data)
+            .ok_or_else(|| format!("Output data is not valid {data_type} {self:?}"))?;
+        Ok(ArrayView::from_shape(shape, data)?)
+    }
 }
 
 impl std::fmt::Debug for OutputData {
@@ -160,18 +224,16 @@ impl std::fmt::Debug for OutputData {
     }
 }
 
-#[cfg(feature="ndarray")]
 fn byte_slice_to<T>(bytes: &[u8]) -> &[T] {
     let data = bytes.as_ptr() as *const T;
     let len = bytes.len();
     let size = std::mem::size_of::<T>();
-    assert!(len % size == 0);
+    assert_eq!(len % size, 0);
     let slice = unsafe { std::slice::from_raw_parts(data, len / size) };
     slice
 }
 
 #[test]
-#[cfg(feature="ndarray")]
 fn test_byte_slice_to() {
     let data: [u8; 12] = [ 0, 0, 0, 0, 1, 2, 3, 4, 246, 0, 255, 255 ];
     // identity
@@ -186,6 +248,42 @@ fn test_byte_slice_to() {
     // float
     assert_eq!(3, byte_slice_to::<f32>(&data).len());
     assert_eq!(0.0, byte_slice_to::<f32>(&data)[0]);
-    assert_eq!(0.0, byte_slice_to::<f32>(&data)[1] - 1.53998969e-36);
+    assert_eq!(0.0, byte_slice_to::<f32>(&data)[1] - 1.539_989_6e-36);
     assert!(byte_slice_to::<f32>(&data)[2].is_nan());
 }
+
+#[test]
+#[cfg(feature="ndarray")]
+fn test_output_data_as_array() {
+    let data: Vec<u8> = (0..6).flat_map(|x: u8| [x]).collect();
+    let output = OutputData {
+        name: "out".to_string(),
+        data_type: DataType::UINT8,
+        shape: vec![2, 3],
+        data,
+        memory_type: triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_CPU,
+        memory_type_id: 0,
+    };
+    assert_eq!(5, output.as_array::<u8, 2>().unwrap()[[1, 2]]);
+    assert!(output.as_array::<u8, 3>().is_err());
+    assert!(output.as_array::<i8, 2>().is_err());
+    assert_eq!(3, output.as_array_dim::<u8, ndarray::Ix2>().unwrap()[(1, 0)]);
+    assert!(output.as_array_dim::<u8, ndarray::Ix1>().is_err());
+}
+
+#[test]
+fn test_inference_response_parameters() {
+    use crate::stub::FakeInferenceResponse;
+    let mut fake = FakeInferenceResponse::new(vec![
+        ("finish_reason", ParameterValue::from("length")),
+        ("completion_tokens", ParameterValue::Int(128)),
+        ("truncated", ParameterValue::Bool(true)),
+    ]);
+    let response = InferenceResponse::from_ptr(fake.as_ptr()).unwrap();
+    assert_eq!(0, response.get_output_count());
+    assert_eq!(3, response.parameters().len());
+    assert_eq!(Some("length"), response.get_parameter("finish_reason").and_then(ParameterValue::as_str));
+    assert_eq!(Some(128), response.get_parameter("completion_tokens").and_then(ParameterValue::as_i64));
+    assert_eq!(Some(&ParameterValue::Bool(true)), response.get_parameter("truncated"));
+    assert!(response.get_parameter("missing").is_none());
+}
@@ -1,32 +1,81 @@
 mod backend;
+mod batch;
+mod bounded;
+mod cancel;
+mod cast;
 mod data_type;
+#[cfg(feature = "dlpack")]
+pub mod dlpack;
+mod generator;
 mod inference_request;
 mod inference_response;
+mod memory_type;
+mod metrics;
 mod model;
 mod model_executor;
 mod model_instance;
+mod parameter;
+mod ragged;
 mod request;
 mod response;
+mod sequence;
 mod server;
+mod state;
+#[cfg(test)]
+mod stub;
 
 pub use backend::Backend;
+pub use batch::Batch;
+pub use bounded::BoundedMetrics;
+pub use bounded::BoundedSender;
+pub use cancel::CancellationToken;
+pub use cast::Cast;
+pub use cast::CastElement;
+pub use cast::Value;
 pub use data_type::DataType;
+pub use data_type::RawBytes;
+pub use data_type::SupportedTypes;
+pub use generator::IntoResponse;
 pub use inference_request::InferenceRequest;
 pub use inference_response::InferenceResponse;
+pub use memory_type::MemoryType;
+pub use metrics::Metric;
+pub use metrics::MetricFamily;
+pub use metrics::MetricKind;
 pub use model_executor::ModelExecutor;
 pub use model_instance::ModelInstance;
 pub use model_instance::ModelInstanceImpl;
 pub use model::Model;
 pub use model::ModelImpl;
+pub use model::TransactionPolicy;
+pub use parameter::ParameterValue;
+pub use ragged::BatchInput;
+pub use ragged::BatchInputKind;
+pub use ragged::BatchInputTensor;
+pub use ragged::RaggedBatch;
+pub use request::CorrelationId;
+pub use request::InputBuffer;
 pub use request::Request;
 pub use request::RequestFlags;
 pub use request::RequestReleaseFlags;
+pub use response::FinalGuard;
 pub use response::Response;
 pub use response::ResponseFactory;
 pub use response::ResponseFlags;
+pub use response::Responder;
+pub use response::ResponseSender;
+pub use sequence::ControlKind;
+pub use sequence::Sequence;
+pub use sequence::SequenceControl;
+pub use sequence::SequenceControls;
+pub use sequence::SequenceStore;
 pub use server::Server;
+pub use state::BufferAttributes;
+pub use state::State;
 pub use triton_sys as sys;
 
+use std::borrow::Cow;
+
 pub type Error = Box<dyn std::error::Error>;
 
 #[allow(non_snake_case)]
@@ -50,45 +99,159 @@ pub(crate) fn check_err(err: *mut triton_sys::TRITONSERVER_Error) -> Result<(),
     }
 }
 
-pub fn decode_string(data: &[u8]) -> Result<Vec<String>, Error> {
-    let mut strings = vec![];
-    let mut i = 0;
-
-    while i < data.len() {
-        // l = struct.unpack_from("<I", val_buf, offset)[0]
-        // offset += 4
-        let wide = u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
-        i += 4;
-
-        // sb = struct.unpack_from("<{}s".format(l), val_buf, offset)[0]
-        // offset += l
-        // strs.append(sb)
-        let bytes = &data[i..i + wide];
-        let string = String::from_utf8_lossy(bytes).to_string();
-        i += wide;
-
-        strings.push(string);
+/// Serialize a TRITONSERVER_Message to JSON, leaving ownership with caller
+pub(crate) fn message_to_json(msg: *mut triton_sys::TRITONSERVER_Message) -> Result<String, Error> {
+    let mut base: *const libc::c_char = std::ptr::null();
+    let mut byte_size: libc::size_t = 0;
+    check_err(unsafe {
+        triton_sys::TRITONSERVER_MessageSerializeToJson(msg, &mut base, &mut byte_size)
+    })?;
+
+    if base.is_null() {
+        return Err("Failed to serialize the message to JSON".into());
     }
 
+    let json = unsafe { std::slice::from_raw_parts(base as *const u8, byte_size) };
+    Ok(String::from_utf8_lossy(json).into_owned())
+}
+
+/// Decode length-prefixed BYTES tensor data. Invalid UTF-8 is replaced lossily.
+pub fn decode_string(data: &[u8]) -> Result<Vec<String>, Error> {
+    let strings = BytesIter::new(data, None)?
+        .map(|bytes| String::from_utf8_lossy(&bytes).to_string())
+        .collect();
     Ok(strings)
 }
 
+/// Iterator over the elements of a BYTES tensor, each encoded as a
+/// little-endian u32 length followed by that many bytes. Elements of borrowed
+/// data are not copied. The whole buffer is validated up front, so malformed
+/// client data is reported as an error rather than panicking halfway through.
+#[derive(Clone, Debug)]
+pub struct BytesIter<'a> {
+    data: Cow<'a, [u8]>,
+    offset: usize,
+    remaining: usize,
+}
+
+impl<'a> BytesIter<'a> {
+    /// Validate `data`, and if given, that it holds exactly `expected_count` elements
+    pub fn new(data: impl Into<Cow<'a, [u8]>>, expected_count: Option<usize>) -> Result<Self, Error> {
+        let data = data.into();
+        let mut count = 0;
+        let mut rest = &data[..];
+        while !rest.is_empty() {
+            let Some((_, tail)) = split_bytes_element(rest) else {
+                let offset = data.len() - rest.len();
+                return Err(format!("Malformed BYTES element {count} at offset {offset}").into());
+            };
+            rest = tail;
+            count += 1;
+        }
+
+        if let Some(expected_count) = expected_count {
+            if count != expected_count {
+                return Err(format!("BYTES tensor holds {count} elements, expected {expected_count}").into());
+            }
+        }
+
+        Ok(Self { data, offset: 0, remaining: count })
+    }
+}
+
+impl<'a> Iterator for BytesIter<'a> {
+    type Item = Cow<'a, [u8]>;
+
+    fn next(&mut self) -> Option<Cow<'a, [u8]>> {
+        let bytes = match self.data {
+            Cow::Borrowed(data) => Cow::Borrowed(split_bytes_element(&data[self.offset..])?.0),
+            Cow::Owned(ref data) => Cow::Owned(split_bytes_element(&data[self.offset..])?.0.to_vec()),
+        };
+        self.offset += encoded_len(&bytes);
+        self.remaining -= 1;
+        Some(bytes)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for BytesIter<'_> {}
+
+fn split_bytes_element(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    // l = struct.unpack_from("<I", val_buf, offset)[0]
+    // offset += 4
+    let (len, rest) = data.split_first_chunk::<4>()?;
+    let len = u32::from_le_bytes(*len) as usize;
+
+    // sb = struct.unpack_from("<{}s".format(l), val_buf, offset)[0]
+    // offset += l
+    // strs.append(sb)
+    if len > rest.len() {
+        return None;
+    }
+    Some(rest.split_at(len))
+}
+
 pub fn encode_string(value: &str) -> Vec<u8> {
-    let mut bytes = vec![];
+    let mut bytes = vec![0; encoded_len(value.as_bytes())];
+    encode_bytes_into(value.as_bytes(), &mut bytes);
+    bytes
+}
 
-    let value: Vec<u8> = value.bytes().collect();
+/// Size of `value` as BYTES tensor element, including length prefix
+pub(crate) fn encoded_len(value: &[u8]) -> usize {
+    std::mem::size_of::<u32>() + value.len()
+}
 
+/// Write `value` as BYTES tensor element into the front of `buffer`, returns
+/// the number of bytes written. `buffer` must hold at least `encoded_len`.
+pub(crate) fn encode_bytes_into(value: &[u8], buffer: &mut [u8]) -> usize {
     // l = struct.unpack_from("<I", val_buf, offset)[0]
     // offset += 4
     let len = (value.len() as u32).to_le_bytes();
-    bytes.extend_from_slice(&len);
+    buffer[..len.len()].copy_from_slice(&len);
 
     // sb = struct.unpack_from("<{}s".format(l), val_buf, offset)[0]
     // offset += l
     // strs.append(sb)
-    bytes.extend_from_slice(&value);
+    buffer[len.len()..len.len() + value.len()].copy_from_slice(value);
 
-    bytes
+    encoded_len(value)
+}
+
+/// Number of elements in a tensor of given shape
+pub(crate) fn element_count(shape: &[i64]) -> Result<usize, Error> {
+    shape.iter().try_fold(1usize, |count, &dim| {
+        let dim = usize::try_from(dim)
+            .map_err(|_| format!("Invalid dimension in shape {shape:?}"))?;
+        count.checked_mul(dim)
+            .ok_or_else(|| format!("Shape overflow {shape:?}").into())
+    })
+}
+
+/// ndarray dimension from a Triton shape, checking rank and refusing negative
+/// (variable) dimensions
+#[cfg(feature = "ndarray")]
+pub(crate) fn array_shape<D: ndarray::Dimension>(shape: &[i64]) -> Result<D, Error> {
+    let dims = shape.iter().map(|&dim| usize::try_from(dim))
+        .collect::<Result<Vec<usize>, _>>()
+        .map_err(|_| format!("Invalid dimension in shape {shape:?}"))?;
+    D::from_dimension(&ndarray::IxDyn(&dims)).ok_or_else(|| {
+        format!("Expected {} dimensions, got shape {shape:?}", D::NDIM.unwrap_or(dims.len())).into()
+    })
+}
+
+/// View bytes as elements of T, if suitably aligned (and valid, for bool)
+pub(crate) fn reinterpret<T: SupportedTypes>(data: &[u8]) -> Option<&[T]> {
+    if <T as SupportedTypes>::of() == DataType::BOOL && data.iter().any(|&b| b > 1) {
+        return None;
+    }
+    data_type::data_type_of::<T>().ok()?;
+    // SAFETY: size was checked by data_type_of, bytes are valid for numeric T
+    let (prefix, data, suffix) = unsafe { data.align_to::<T>() };
+    (prefix.is_empty() && suffix.is_empty()).then_some(data)
 }
 
 fn into_error(err: *mut triton_sys::TRITONSERVER_Error) -> Error {
@@ -103,3 +266,26 @@ fn into_error(err: *mut triton_sys::TRITONSERVER_Error) -> Error {
     format!("TRITONSERVER_Error: {msg:?} ({code})").into()
 }
 
+
+#[test]
+fn test_decode_string() {
+    let mut data = encode_string("foo");
+    data.extend(encode_string(""));
+    data.extend(encode_string("bär"));
+    assert_eq!(vec!["foo", "", "bär"], decode_string(&data).unwrap());
+    let elements: Vec<Cow<[u8]>> = BytesIter::new(&data[..], Some(3)).unwrap().collect();
+    assert_eq!(elements, vec![&b"foo"[..], b"", "bär".as_bytes()]);
+    assert!(elements.iter().all(|bytes| matches!(bytes, Cow::Borrowed(_))));
+    let elements: Vec<Cow<[u8]>> = BytesIter::new(data.clone(), Some(3)).unwrap().collect();
+    assert_eq!(elements, vec![&b"foo"[..], b"", "bär".as_bytes()]);
+    assert_eq!(3, BytesIter::new(&data[..], None).unwrap().len());
+    assert!(BytesIter::new(&data[..], Some(2)).is_err());
+    assert!(decode_string(&[]).unwrap().is_empty());
+
+    // truncated length prefix
+    assert!(decode_string(&data[..data.len() - 6]).is_err());
+    // truncated element
+    assert!(decode_string(&data[..data.len() - 1]).is_err());
+    // corrupt length prefix
+    assert!(decode_string(&[255, 255, 255, 255, b'x']).is_err());
+}
@@ -0,0 +1,63 @@
+use crate::Error;
+use std::ffi::CStr;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug,Clone,Copy,PartialEq)]
+#[repr(u32)]
+pub enum MemoryType {
+    CPU = triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_CPU,
+    CPU_PINNED = triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_CPU_PINNED,
+    GPU = triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_GPU,
+}
+
+impl MemoryType {
+    /// Whether memory of this type can be dereferenced on the host
+    pub fn is_cpu(&self) -> bool {
+        matches!(self, Self::CPU | Self::CPU_PINNED)
+    }
+
+    /// Refuse buffers that cannot be dereferenced on the host
+    pub(crate) fn check_cpu(&self, what: &str) -> Result<(), Error> {
+        if !self.is_cpu() {
+            return Err(format!("{what} is in {self} memory, expected CPU").into());
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<u32> for MemoryType {
+    type Error = Error;
+
+    fn try_from(v: u32) -> Result<MemoryType, Error> {
+        match v {
+            triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_CPU => Ok(Self::CPU),
+            triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_CPU_PINNED => Ok(Self::CPU_PINNED),
+            triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_GPU => Ok(Self::GPU),
+            _ => Err(format!("Unknown memory type {v}").into()),
+        }
+    }
+}
+
+impl From<&MemoryType> for u32 { fn from(v: &MemoryType) -> u32 { *v as u32 } }
+impl std::fmt::Display for MemoryType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cstr = unsafe {
+            // The returned string is not owned by the caller and so should not be
+            // modified or freed.
+            let char_ptr = triton_sys::TRITONSERVER_MemoryTypeString(self.into());
+            CStr::from_ptr(char_ptr)
+        };
+        f.write_str(&cstr.to_string_lossy())
+    }
+}
+
+#[test]
+fn test_memory_type() {
+    assert_eq!(MemoryType::CPU, 0.try_into().unwrap());
+    assert_eq!(MemoryType::CPU_PINNED, 1.try_into().unwrap());
+    assert_eq!(MemoryType::GPU, 2.try_into().unwrap());
+    assert!(MemoryType::try_from(3).is_err());
+    assert!(MemoryType::CPU_PINNED.check_cpu("buffer").is_ok());
+    let err = MemoryType::GPU.check_cpu("buffer").unwrap_err();
+    assert_eq!("buffer is in GPU memory, expected CPU", err.to_string());
+}
@@ -0,0 +1,151 @@
+//! Custom metrics, reported by Triton's metrics endpoint next to its own.
+
+use crate::{check_err, Error};
+use std::ffi::{c_void, CString};
+use std::ptr;
+use std::sync::Arc;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+/// Metric name and description, shared by the metrics of each label set
+pub struct MetricFamily {
+    ptr: *mut triton_sys::TRITONSERVER_MetricFamily,
+}
+
+// SAFETY: Triton metrics are thread-safe, families are only created and deleted
+unsafe impl Send for MetricFamily {}
+unsafe impl Sync for MetricFamily {}
+
+impl MetricFamily {
+    /// Family `name`, e.g. "backend_queue_depth", must be unique within Triton
+    pub fn new(kind: MetricKind, name: &str, description: &str) -> Result<Arc<Self>, Error> {
+        let kind = match kind {
+            MetricKind::Counter => triton_sys::TRITONSERVER_metrickind_enum_TRITONSERVER_METRIC_KIND_COUNTER,
+            MetricKind::Gauge => triton_sys::TRITONSERVER_metrickind_enum_TRITONSERVER_METRIC_KIND_GAUGE,
+        };
+        let name = CString::new(name)?;
+        let description = CString::new(description)?;
+        let mut family: *mut triton_sys::TRITONSERVER_MetricFamily = ptr::null_mut();
+        check_err(unsafe {
+            triton_sys::TRITONSERVER_MetricFamilyNew(&mut family, kind, name.as_ptr(), description.as_ptr())
+        })?;
+        Ok(Arc::new(Self { ptr: family }))
+    }
+}
+
+impl Drop for MetricFamily {
+    fn drop(&mut self) {
+        // Drop cannot fail, check_err reports the error
+        let _ = check_err(unsafe { triton_sys::TRITONSERVER_MetricFamilyDelete(self.ptr) });
+    }
+}
+
+/// Metric of a family with a set of labels, e.g. `[("model", "gpt")]`.
+/// Keeps the family alive, as Triton deletes its metrics before the family.
+pub struct Metric {
+    ptr: *mut triton_sys::TRITONSERVER_Metric,
+    _family: Arc<MetricFamily>,
+}
+
+// SAFETY: see MetricFamily
+unsafe impl Send for Metric {}
+unsafe impl Sync for Metric {}
+
+impl Metric {
+    pub fn new(family: &Arc<MetricFamily>, labels: &[(&str, &str)]) -> Result<Self, Error> {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| Ok((CString::new(*name)?, CString::new(*value)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+        // Triton copies the labels, so the parameters only live for MetricNew
+        let mut parameters: Vec<*const triton_sys::TRITONSERVER_Parameter> = Vec::with_capacity(labels.len());
+        let mut result = Ok(());
+        for (name, value) in &labels {
+            let parameter = unsafe {
+                triton_sys::TRITONSERVER_ParameterNew(
+                    name.as_ptr(),
+                    triton_sys::TRITONSERVER_parametertype_enum_TRITONSERVER_PARAMETER_STRING,
+                    value.as_ptr() as *const c_void,
+                )
+            };
+            if parameter.is_null() {
+                result = Err(format!("Failed to create the metric label {name:?}").into());
+                break;
+            }
+            parameters.push(parameter as *const _);
+        }
+
+        let mut metric: *mut triton_sys::TRITONSERVER_Metric = ptr::null_mut();
+        if result.is_ok() {
+            result = check_err(unsafe {
+                triton_sys::TRITONSERVER_MetricNew(
+                    &mut metric,
+                    family.ptr,
+                    parameters.as_mut_ptr(),
+                    parameters.len() as u64,
+                )
+            });
+        }
+        for parameter in parameters {
+            unsafe { triton_sys::TRITONSERVER_ParameterDelete(parameter as *mut _) };
+        }
+        result?;
+        Ok(Self { ptr: metric, _family: family.clone() })
+    }
+
+    pub fn value(&self) -> Result<f64, Error> {
+        let mut value = 0.0;
+        check_err(unsafe { triton_sys::TRITONSERVER_MetricValue(self.ptr, &mut value) })?;
+        Ok(value)
+    }
+
+    /// Add `value`, which must not be negative for counters
+    pub fn increment(&self, value: f64) -> Result<(), Error> {
+        check_err(unsafe { triton_sys::TRITONSERVER_MetricIncrement(self.ptr, value) })
+    }
+
+    /// Set the value of a gauge
+    pub fn set(&self, value: f64) -> Result<(), Error> {
+        check_err(unsafe { triton_sys::TRITONSERVER_MetricSet(self.ptr, value) })
+    }
+}
+
+impl Drop for Metric {
+    fn drop(&mut self) {
+        let _ = check_err(unsafe { triton_sys::TRITONSERVER_MetricDelete(self.ptr) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::{FakeMetric, FakeMetricFamily};
+
+    #[test]
+    fn test_metric() {
+        let family = MetricFamily::new(MetricKind::Gauge, "queue_depth", "Responses queued").unwrap();
+        let fake_family = unsafe { &*(family.ptr as *const FakeMetricFamily) };
+        assert_eq!(("queue_depth", "Responses queued"), (fake_family.name.as_str(), fake_family.description.as_str()));
+
+        let metric = Metric::new(&family, &[("model", "gpt"), ("version", "1")]).unwrap();
+        let fake = unsafe { &*(metric.ptr as *const FakeMetric) };
+        assert_eq!(vec![("model".to_string(), "gpt".to_string()), ("version".into(), "1".into())], fake.labels);
+        metric.increment(2.0).unwrap();
+        metric.increment(1.5).unwrap();
+        assert_eq!(3.5, metric.value().unwrap());
+        metric.set(1.0).unwrap();
+        assert_eq!(1.0, metric.value().unwrap());
+
+        let counter = MetricFamily::new(MetricKind::Counter, "responses", "Responses sent").unwrap();
+        assert!(Metric::new(&counter, &[("model", "")]).is_err());
+        let metric = Metric::new(&counter, &[]).unwrap();
+        assert!(metric.increment(-1.0).is_err());
+        assert!(metric.set(1.0).is_err());
+        drop(counter);
+        metric.increment(1.0).unwrap();
+    }
+}
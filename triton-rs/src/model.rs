@@ -1,6 +1,6 @@
 use crate::{check_err, Error, Server};
 use libc::{c_char, size_t};
-use std::ffi::{c_void, CStr};
+use std::ffi::{c_void, CStr, CString};
 use std::fs::File;
 use std::io::prelude::*;
 use std::marker::PhantomData;
@@ -9,51 +9,61 @@ use std::ptr;
 
 pub trait Model {
     type S;
+    #[allow(clippy::mut_from_ref)]
     fn state(&self) -> Result<&mut Self::S, Error>;
     fn replace_state(&self, new_state: Option<Self::S>) -> Result<Option<Self::S>, Error>;
 }
 
+/// `model_transaction_policy` of a model
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransactionPolicy {
+    /// Exactly one response per request
+    OneToOne,
+    /// Zero or more responses per request, then the FINAL flag
+    Decoupled,
+}
+
+impl TransactionPolicy {
+    /// Policy of a model config, as JSON from `Model::model_config`
+    pub fn from_model_config(model_config: &str) -> Result<Self, Error> {
+        let config: serde_json::Value = serde_json::from_str(model_config)?;
+        let decoupled = config.get("model_transaction_policy")
+            .and_then(|policy| policy.get("decoupled"))
+            .map(|decoupled| decoupled.as_bool().ok_or_else(|| format!("Invalid decoupled {decoupled}")))
+            .transpose()?
+            .unwrap_or(false);
+        Ok(if decoupled { Self::Decoupled } else { Self::OneToOne })
+    }
+
+    pub fn is_decoupled(&self) -> bool {
+        *self == Self::Decoupled
+    }
+}
+
 pub struct ModelImpl<S> {
     ptr: *mut triton_sys::TRITONBACKEND_Model,
     _state: PhantomData<S>,
 }
 
+/// What a model keeps as its Triton state: the backend's state, and the
+/// transaction policy resolved when the model was initialized
+struct ModelData<S> {
+    transaction_policy: TransactionPolicy,
+    state: Option<S>,
+}
+
 impl<S> Model for ModelImpl<S> {
     type S = S;
 
     fn state(&self) -> Result<&mut Self::S, Error> {
-        let state = self.raw_state()?;
-        if state.is_null() {
-            return Err("Failed to get the state pointer".into());
-        }
-
-        let state: *mut Self::S = state as _;
-        let state: &mut Self::S = unsafe { state.as_mut() }.unwrap();
-
-        Ok(state)
+        let data = self.data()?;
+        data.state.as_mut().ok_or_else(|| "Failed to get the state pointer".into())
     }
 
     fn replace_state(&self, new_state: Option<Self::S>)
             -> Result<Option<Self::S>, Error> {
-        let old_state = self.raw_state()?;
-
-        let new_state = match new_state {
-            Some(new_state) => {
-                let new_state = Box::new(new_state);
-                Box::<Self::S>::into_raw(new_state) as *mut c_void
-            },
-            None => ptr::null_mut()
-        };
-        check_err(unsafe {
-            triton_sys::TRITONBACKEND_ModelSetState(self.ptr, new_state)
-        })?;
-
-        if old_state.is_null() {
-            return Ok(None);
-        }
-
-        let old_state = unsafe { Box::<Self::S>::from_raw(old_state) };
-        Ok(Some(*old_state))
+        let data = self.data()?;
+        Ok(std::mem::replace(&mut data.state, new_state))
     }
 }
 
@@ -144,11 +154,100 @@ impl<S> ModelImpl<S> {
         Ok(json_str)
     }
 
-    fn raw_state(&self) -> Result<*mut S, Error> {
-        let mut state : *mut c_void = ptr::null_mut();
+    /// Resolve the transaction policy and set up the model state, called by
+    /// `declare_backend!` before `Backend::model_initialize`
+    pub fn initialize(&self) -> Result<(), Error> {
+        self.attach(self.query_transaction_policy()?)
+    }
+
+    /// Drop the model state, called by `declare_backend!` after
+    /// `Backend::model_finalize`
+    pub fn finalize(&self) -> Result<(), Error> {
+        let data = self.raw_data()?;
+        check_err(unsafe { triton_sys::TRITONBACKEND_ModelSetState(self.ptr, ptr::null_mut()) })?;
+        if !data.is_null() {
+            drop(unsafe { Box::from_raw(data) });
+        }
+        Ok(())
+    }
+
+    /// Transaction policy as loaded by the server, resolved once when the
+    /// model was initialized
+    pub fn transaction_policy(&self) -> Result<TransactionPolicy, Error> {
+        let data = unsafe { self.raw_data()?.as_ref() };
+        data.map(|data| data.transaction_policy).ok_or_else(|| "Model is not initialized".into())
+    }
+
+    fn attach(&self, transaction_policy: TransactionPolicy) -> Result<(), Error> {
+        if !self.raw_data()?.is_null() {
+            return Err("Model is already initialized".into());
+        }
+        let data = Box::new(ModelData::<S> { transaction_policy, state: None });
+        check_err(unsafe {
+            triton_sys::TRITONBACKEND_ModelSetState(self.ptr, Box::into_raw(data) as *mut c_void)
+        })
+    }
+
+    fn query_transaction_policy(&self) -> Result<TransactionPolicy, Error> {
+        let name = CString::new(self.name()?)?;
+        let mut flags = 0u32;
+        check_err(unsafe {
+            triton_sys::TRITONSERVER_ServerModelTransactionProperties(
+                self.server()?.as_ptr(),
+                name.as_ptr(),
+                self.version()?.try_into()?,
+                &mut flags,
+                ptr::null_mut(),
+            )
+        })?;
+        if flags & triton_sys::tritonserver_txn_property_flag_enum_TRITONSERVER_TXN_DECOUPLED != 0 {
+            Ok(TransactionPolicy::Decoupled)
+        } else {
+            Ok(TransactionPolicy::OneToOne)
+        }
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn data(&self) -> Result<&mut ModelData<S>, Error> {
+        let data = unsafe { self.raw_data()?.as_mut() };
+        data.ok_or_else(|| "Model is not initialized".into())
+    }
+
+    fn raw_data(&self) -> Result<*mut ModelData<S>, Error> {
+        let mut data : *mut c_void = ptr::null_mut();
         check_err(unsafe {
-            triton_sys::TRITONBACKEND_ModelState(self.ptr, &mut state)
+            triton_sys::TRITONBACKEND_ModelState(self.ptr, &mut data)
         })?;
-        Ok(state as *mut S)
+        Ok(data as *mut ModelData<S>)
     }
 }
+
+#[test]
+fn test_transaction_policy_from_model_config() {
+    let policy = |config| TransactionPolicy::from_model_config(config).unwrap();
+    assert_eq!(TransactionPolicy::Decoupled, policy(r#"{"model_transaction_policy": {"decoupled": true}}"#));
+    assert_eq!(TransactionPolicy::OneToOne, policy(r#"{"model_transaction_policy": {"decoupled": false}}"#));
+    assert_eq!(TransactionPolicy::OneToOne, policy(r#"{"name": "m"}"#));
+    assert!(policy(r#"{"model_transaction_policy": {"decoupled": true}}"#).is_decoupled());
+    assert!(TransactionPolicy::from_model_config(r#"{"model_transaction_policy": {"decoupled": 1}}"#).is_err());
+}
+
+#[test]
+fn test_model_data() {
+    let mut fake = crate::stub::FakeModel::default();
+    let model = ModelImpl::<String>::from_ptr(fake.as_ptr());
+    assert!(model.transaction_policy().is_err());
+    assert!(model.state().is_err());
+
+    model.attach(TransactionPolicy::Decoupled).unwrap();
+    assert!(model.attach(TransactionPolicy::OneToOne).is_err());
+    assert_eq!(TransactionPolicy::Decoupled, model.transaction_policy().unwrap());
+    assert_eq!(None, model.replace_state(Some("state".to_string())).unwrap());
+    model.state().unwrap().push('!');
+    assert_eq!(Some("state!".to_string()), model.replace_state(None).unwrap());
+    assert_eq!(TransactionPolicy::Decoupled, model.transaction_policy().unwrap());
+
+    model.finalize().unwrap();
+    assert!(fake.state.is_null());
+    assert!(model.transaction_policy().is_err());
+}
@@ -196,6 +196,13 @@ extern "C" fn response_release(
     ptr::null_mut() // Success
 }
 
+// TEST STUB
+#[cfg(test)]
+#[allow(non_snake_case)]
+pub fn to_TRITONSERVER_Error(_err: Error) -> *mut triton_sys::TRITONSERVER_Error {
+    std::ptr::NonNull::<triton_sys::TRITONSERVER_Error>::dangling().as_ptr()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -299,10 +306,3 @@ mod tests {
 //    }
 }
 
-// TEST STUB
-#[cfg(test)]
-#[allow(non_snake_case)]
-pub fn to_TRITONSERVER_Error(_err: Error) -> *mut triton_sys::TRITONSERVER_Error {
-    std::ptr::NonNull::<triton_sys::TRITONSERVER_Error>::dangling().as_ptr()
-}
-
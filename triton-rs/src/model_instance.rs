@@ -1,9 +1,10 @@
 use crate::{check_err, Error};
 use crate::model::ModelImpl;
-use std::{ffi::c_void, marker::PhantomData, ptr};
+use std::{ffi::c_void, ffi::CStr, marker::PhantomData, ptr, sync::Arc};
 
 pub trait ModelInstance {
     type S;
+    #[allow(clippy::mut_from_ref)]
     fn state(&self) -> Result<&mut Self::S, Error>;
     fn replace_state(&self, new_state: Option<Self::S>) -> Result<Option<Self::S>, Error>;
 }
@@ -14,43 +15,26 @@ pub struct ModelInstanceImpl<ModelInstanceState, ModelState> {
     _model_state: PhantomData<ModelState>,
 }
 
+/// What an instance keeps as its Triton state: the backend's state, and the
+/// host policy name resolved when the instance was initialized
+struct InstanceData<S> {
+    host_policy_name: Option<Arc<CStr>>,
+    state: Option<S>,
+}
+
 impl<ModelInstanceState, ModelState> ModelInstance
         for ModelInstanceImpl<ModelInstanceState, ModelState> {
     type S = ModelInstanceState;
 
     fn state(&self) -> Result<&mut Self::S, Error> {
-        let state = self.raw_state()?;
-        if state.is_null() {
-            return Err("Failed to get the state pointer".into());
-        }
-
-        let state: *mut Self::S = state as _;
-        let state: &mut Self::S = unsafe { state.as_mut() }.unwrap();
-
-        Ok(state)
+        let data = self.data()?;
+        data.state.as_mut().ok_or_else(|| "Failed to get the state pointer".into())
     }
 
     fn replace_state(&self, new_state: Option<Self::S>)
             -> Result<Option<Self::S>, Error> {
-        let old_state = self.raw_state()?;
-
-        let new_state = match new_state {
-            Some(new_state) => {
-                let new_state = Box::new(new_state);
-                Box::<Self::S>::into_raw(new_state) as *mut c_void
-            },
-            None => ptr::null_mut()
-        };
-        check_err(unsafe {
-            triton_sys::TRITONBACKEND_ModelInstanceSetState(self.ptr, new_state)
-        })?;
-
-        if old_state.is_null() {
-            return Ok(None);
-        }
-
-        let old_state = unsafe { Box::<Self::S>::from_raw(old_state) };
-        Ok(Some(*old_state))
+        let data = self.data()?;
+        Ok(std::mem::replace(&mut data.state, new_state))
     }
 }
 
@@ -73,12 +57,109 @@ impl<ModelInstanceState, ModelState> ModelInstanceImpl<ModelInstanceState, Model
         Ok(ModelImpl::from_ptr(model))
     }
 
-    fn raw_state(&self) -> Result<*mut ModelInstanceState, Error> {
-        let mut state : *mut c_void = ptr::null_mut();
+    /// Host policy setting of this instance, as JSON:
+    /// `{ "<host_policy>": { "<setting>": "<value>", ... } }`
+    pub fn host_policy(&self) -> Result<String, Error> {
+        let mut msg : *mut triton_sys::TRITONSERVER_Message = ptr::null_mut();
+        check_err(unsafe {
+            triton_sys::TRITONBACKEND_ModelInstanceHostPolicy(self.ptr, &mut msg)
+        })?;
+
+        if msg.is_null() {
+            return Err("Failed to get the host policy message pointer".into());
+        }
+
+        // message is owned by Triton, don't delete
+        crate::message_to_json(msg)
+    }
+
+    /// Resolve the host policy name and set up the instance state, called by
+    /// `declare_backend!` before `Backend::model_instance_initialize`
+    pub fn initialize(&self) -> Result<(), Error> {
+        let name = host_policy_name(&self.host_policy()?)?;
+        self.attach(name.map(Arc::from))
+    }
+
+    /// Drop the instance state, called by `declare_backend!` after
+    /// `Backend::model_instance_finalize`
+    pub fn finalize(&self) -> Result<(), Error> {
+        let data = self.raw_data()?;
+        check_err(unsafe { triton_sys::TRITONBACKEND_ModelInstanceSetState(self.ptr, ptr::null_mut()) })?;
+        if !data.is_null() {
+            drop(unsafe { Box::from_raw(data) });
+        }
+        Ok(())
+    }
+
+    /// Name of the host policy this instance is bound to, if any, resolved
+    /// once when the instance was initialized
+    pub fn host_policy_name(&self) -> Result<Option<Arc<CStr>>, Error> {
+        let data = unsafe { self.raw_data()?.as_ref() };
+        data.map(|data| data.host_policy_name.clone()).ok_or_else(|| "Model instance is not initialized".into())
+    }
+
+    fn attach(&self, host_policy_name: Option<Arc<CStr>>) -> Result<(), Error> {
+        if !self.raw_data()?.is_null() {
+            return Err("Model instance is already initialized".into());
+        }
+        let data = Box::new(InstanceData::<ModelInstanceState> { host_policy_name, state: None });
+        check_err(unsafe {
+            triton_sys::TRITONBACKEND_ModelInstanceSetState(self.ptr, Box::into_raw(data) as *mut c_void)
+        })
+    }
+
+    #[allow(clippy::mut_from_ref)]
+    fn data(&self) -> Result<&mut InstanceData<ModelInstanceState>, Error> {
+        let data = unsafe { self.raw_data()?.as_mut() };
+        data.ok_or_else(|| "Model instance is not initialized".into())
+    }
+
+    fn raw_data(&self) -> Result<*mut InstanceData<ModelInstanceState>, Error> {
+        let mut data : *mut c_void = ptr::null_mut();
         check_err(unsafe {
-            triton_sys::TRITONBACKEND_ModelInstanceState(self.ptr, &mut state)
+            triton_sys::TRITONBACKEND_ModelInstanceState(self.ptr, &mut data)
         })?;
-        Ok(state as *mut ModelInstanceState)
+        Ok(data as *mut InstanceData<ModelInstanceState>)
     }
 }
 
+fn host_policy_name(json: &str) -> Result<Option<std::ffi::CString>, Error> {
+    let policy: serde_json::Value = serde_json::from_str(json)?;
+    let Some(policy) = policy.as_object() else {
+        return Err(format!("Malformed host policy {json}").into());
+    };
+    let mut names = policy.keys();
+    match (names.next(), names.next()) {
+        (None, _) => Ok(None),
+        (Some(name), None) => Ok(Some(std::ffi::CString::new(name.as_str())?)),
+        (Some(_), Some(_)) => Err(format!("Expected single host policy {json}").into()),
+    }
+}
+
+#[test]
+fn test_host_policy_name() {
+    let json = r#"{"gpu_0":{"numa_node":"0","cpu_cores":"0-15"}}"#;
+    assert_eq!(Some(c"gpu_0".into()), host_policy_name(json).unwrap());
+    assert_eq!(None, host_policy_name("{}").unwrap());
+    assert!(host_policy_name(r#"{"cpu":{},"gpu_0":{}}"#).is_err());
+    assert!(host_policy_name("[]").is_err());
+}
+
+#[test]
+fn test_instance_data() {
+    let mut fake = crate::stub::FakeModelInstance::default();
+    let instance = ModelInstanceImpl::<u32, ()>::from_ptr(fake.as_ptr());
+    assert!(instance.host_policy_name().is_err());
+
+    instance.attach(Some(c"gpu_0".into())).unwrap();
+    assert!(instance.attach(None).is_err());
+    assert_eq!(Some(c"gpu_0".into()), instance.host_policy_name().unwrap());
+    assert_eq!(None, instance.replace_state(Some(1)).unwrap());
+    *instance.state().unwrap() += 1;
+    assert_eq!(Some(2), instance.replace_state(None).unwrap());
+    assert!(instance.state().is_err());
+
+    instance.finalize().unwrap();
+    assert!(fake.state.is_null());
+    assert!(instance.host_policy_name().is_err());
+}
@@ -0,0 +1,54 @@
+/// Value of a response parameter, e.g. a token count or finish reason
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParameterValue {
+    String(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl ParameterValue {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            ParameterValue::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            ParameterValue::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            ParameterValue::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for ParameterValue {
+    fn from(value: &str) -> ParameterValue {
+        ParameterValue::String(value.to_string())
+    }
+}
+
+impl From<String> for ParameterValue {
+    fn from(value: String) -> ParameterValue {
+        ParameterValue::String(value)
+    }
+}
+
+impl From<i64> for ParameterValue {
+    fn from(value: i64) -> ParameterValue {
+        ParameterValue::Int(value)
+    }
+}
+
+impl From<bool> for ParameterValue {
+    fn from(value: bool) -> ParameterValue {
+        ParameterValue::Bool(value)
+    }
+}
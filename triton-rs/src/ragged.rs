@@ -0,0 +1,335 @@
+//! Ragged batching, for models with `allow_ragged_batch`: inputs of different
+//! shapes are concatenated without padding, and batch inputs from the model
+//! config's `batch_input` section describe where each batch item starts.
+//! https://github.com/triton-inference-server/server/blob/main/docs/user_guide/ragged_batching.md
+
+use crate::batch::{append_input, batch_dims, check_responses, send_errors};
+use crate::data_type::{data_type_of, SupportedTypes};
+use crate::{DataType, Error, Request, Response, ResponseFlags};
+use std::ops::Range;
+
+/// Variable size input of all requests, flattened and concatenated
+pub struct RaggedBatch<T> {
+    pub name: String,
+    pub data: Vec<T>,
+    /// Input shape per request (with batch dimension), None for failed requests
+    shapes: Vec<Option<Vec<i64>>>,
+    /// Elements in `data` per request
+    elements: Vec<Range<usize>>,
+    errors: Vec<Option<Error>>,
+}
+
+impl<T: Copy + SupportedTypes> RaggedBatch<T> {
+    /// Gather input `name` of all requests. A request whose input is missing or
+    /// invalid fails on its own, see `send_errors`.
+    pub fn gather(requests: &[Request], name: &str) -> Result<Self, Error> {
+        let data_type = data_type_of::<T>()?;
+        let mut batch = Self { name: name.to_string(), data: vec![], shapes: vec![], elements: vec![], errors: vec![] };
+        for request in requests {
+            let start = batch.data.len();
+            let shape = append_input(&mut batch.data, request, name, data_type, |properties| {
+                batch_dims(name, properties).map(|_| ())
+            });
+            batch.elements.push(start..batch.data.len());
+            match shape {
+                Ok(shape) => {
+                    batch.shapes.push(Some(shape));
+                    batch.errors.push(None);
+                },
+                Err(err) => {
+                    batch.shapes.push(None);
+                    batch.errors.push(Some(err));
+                },
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Split ragged output into an output `name` on the response of each
+    /// request, skipping failed requests. The output of a request has the shape
+    /// of its input followed by `inner_shape`. A response that cannot take its
+    /// output is sent with the error and taken.
+    pub fn scatter<U>(&self, responses: &mut [Option<Response>], name: &str, inner_shape: &[i64], data: &[U])
+            -> Result<(), Error> where U: Copy + SupportedTypes {
+        check_responses(self.shapes.len(), responses)?;
+        let inner_len = crate::element_count(inner_shape)?;
+        if data.len() != self.data.len() * inner_len {
+            return Err(format!("Output {name} has {} elements, expected {} x {inner_shape:?}",
+                               data.len(), self.data.len()).into());
+        }
+        let requests = self.shapes.iter().zip(&self.elements).zip(responses.iter_mut());
+        for ((shape, elements), response) in requests {
+            let (Some(shape), Some(output)) = (shape, response.as_mut()) else {
+                continue;
+            };
+            let output_shape: Vec<i64> = shape.iter().chain(inner_shape).copied().collect();
+            let data = &data[elements.start * inner_len..elements.end * inner_len];
+            if let Err(err) = output.add_output(name, &output_shape, data) {
+                response.take().unwrap().send(ResponseFlags::FINAL, Some(err))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> RaggedBatch<T> {
+    /// Shape of the concatenated data
+    pub fn shape(&self) -> Vec<i64> {
+        vec![self.data.len() as i64]
+    }
+
+    /// Elements of request `index` in `data`, None if it failed
+    pub fn elements(&self, index: usize) -> Option<Range<usize>> {
+        self.shapes.get(index)?.as_ref()?;
+        self.elements.get(index).cloned()
+    }
+
+    pub fn is_failed(&self, index: usize) -> bool {
+        self.shapes.get(index).is_some_and(|shape| shape.is_none())
+    }
+
+    /// Send an error response for each request that failed to gather, taking
+    /// its response out of `responses`
+    pub fn send_errors(&mut self, responses: &mut [Option<Response>]) -> Result<(), Error> {
+        send_errors(&mut self.errors, responses)
+    }
+
+    /// Shape of each batch item, i.e. request shapes without batch dimension,
+    /// repeated for the batch size of the request
+    fn item_shapes(&self) -> impl Iterator<Item = &[i64]> {
+        self.shapes.iter().flatten()
+            .flat_map(|shape| std::iter::repeat_n(&shape[1..], shape[0] as usize))
+    }
+
+    /// Compute batch input `config`, whose source input must be this batch
+    pub fn batch_input(&self, config: &BatchInput) -> Result<BatchInputTensor, Error> {
+        if config.source_input.first() != Some(&self.name) {
+            return Err(format!("Batch input {} is not sourced from {}", config.target_name, self.name).into());
+        }
+        let counts: Vec<i64> = self.item_shapes()
+            .map(|shape| crate::element_count(shape).map(|count| count as i64))
+            .collect::<Result<_, _>>()?;
+        let accumulated = counts.iter().scan(0, |sum, count| {
+            *sum += count;
+            Some(*sum)
+        });
+        let (shape, values): (Vec<i64>, Vec<i64>) = match config.kind {
+            BatchInputKind::ElementCount => (vec![counts.len() as i64], counts),
+            BatchInputKind::AccumulatedElementCount => (vec![counts.len() as i64], accumulated.collect()),
+            BatchInputKind::AccumulatedElementCountWithZero => {
+                (vec![counts.len() as i64 + 1], std::iter::once(0).chain(accumulated).collect())
+            },
+            BatchInputKind::MaxElementCountAsShape => {
+                let max = counts.iter().copied().max().unwrap_or(0);
+                (vec![max], vec![0; max as usize])
+            },
+            BatchInputKind::ItemShape | BatchInputKind::ItemShapeFlatten => {
+                let mut dims = self.item_shapes().map(|shape| shape.len());
+                let ndim = dims.next().unwrap_or(0);
+                if dims.any(|n| n != ndim) {
+                    return Err(format!("Batch input {} needs inputs of equal rank", config.target_name).into());
+                }
+                let values: Vec<i64> = self.item_shapes().flatten().copied().collect();
+                match config.kind {
+                    BatchInputKind::ItemShape => (vec![counts.len() as i64, ndim as i64], values),
+                    _ => (vec![values.len() as i64], values),
+                }
+            },
+        };
+        Ok(BatchInputTensor {
+            name: config.target_name.clone(),
+            data_type: config.data_type,
+            shape,
+            data: encode_values(config.data_type, &values)?,
+        })
+    }
+}
+
+fn encode_values(data_type: DataType, values: &[i64]) -> Result<Vec<u8>, Error> {
+    let data = match data_type {
+        DataType::INT32 => values.iter().map(|&x| i32::try_from(x)).collect::<Result<Vec<_>, _>>()?
+            .iter().flat_map(|x| x.to_ne_bytes()).collect(),
+        DataType::INT64 => values.iter().flat_map(|x| x.to_ne_bytes()).collect(),
+        DataType::FP32 => values.iter().flat_map(|&x| (x as f32).to_ne_bytes()).collect(),
+        DataType::FP64 => values.iter().flat_map(|&x| (x as f64).to_ne_bytes()).collect(),
+        _ => return Err(format!("Batch input of {data_type} not supported").into()),
+    };
+    Ok(data)
+}
+
+/// Kind of a batch input, see the model configuration docs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BatchInputKind {
+    /// Element count of each batch item
+    ElementCount,
+    /// Element count of each batch item, accumulated
+    AccumulatedElementCount,
+    /// Like AccumulatedElementCount, starting with 0
+    AccumulatedElementCountWithZero,
+    /// Shape is the largest element count of the batch items, data is ignored
+    MaxElementCountAsShape,
+    /// Shape of each batch item, as [batch items, dimensions]
+    ItemShape,
+    /// Like ItemShape, flattened
+    ItemShapeFlatten,
+}
+
+impl std::str::FromStr for BatchInputKind {
+    type Err = Error;
+
+    fn from_str(kind: &str) -> Result<BatchInputKind, Error> {
+        let kind = match kind {
+            "BATCH_ELEMENT_COUNT" => Self::ElementCount,
+            "BATCH_ACCUMULATED_ELEMENT_COUNT" => Self::AccumulatedElementCount,
+            "BATCH_ACCUMULATED_ELEMENT_COUNT_WITH_ZERO" => Self::AccumulatedElementCountWithZero,
+            "BATCH_MAX_ELEMENT_COUNT_AS_SHAPE" => Self::MaxElementCountAsShape,
+            "BATCH_ITEM_SHAPE" => Self::ItemShape,
+            "BATCH_ITEM_SHAPE_FLATTEN" => Self::ItemShapeFlatten,
+            _ => return Err(format!("Unknown batch input kind {kind:?}").into()),
+        };
+        Ok(kind)
+    }
+}
+
+/// Entry of the `batch_input` section of the model config
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchInput {
+    pub kind: BatchInputKind,
+    pub target_name: String,
+    pub data_type: DataType,
+    pub source_input: Vec<String>,
+}
+
+impl BatchInput {
+    /// All batch inputs of a model config, as JSON from `Model::model_config`
+    pub fn from_model_config(model_config: &str) -> Result<Vec<BatchInput>, Error> {
+        let config: serde_json::Value = serde_json::from_str(model_config)?;
+        let Some(batch_inputs) = config.get("batch_input") else {
+            return Ok(vec![]);
+        };
+        let batch_inputs = batch_inputs.as_array().ok_or("batch_input is not an array")?;
+        batch_inputs.iter().map(Self::from_json).collect()
+    }
+
+    fn from_json(json: &serde_json::Value) -> Result<BatchInput, Error> {
+        let field = |name: &str| {
+            json.get(name).ok_or_else(|| format!("batch_input without {name} {json}"))
+        };
+        let strings = |name: &str| -> Result<Vec<String>, Error> {
+            field(name)?.as_array().ok_or_else(|| format!("batch_input {name} is not an array {json}"))?
+                .iter()
+                .map(|value| value.as_str().map(str::to_string)
+                    .ok_or_else(|| format!("batch_input {name} is not a string {json}").into()))
+                .collect()
+        };
+        let as_str = |name: &str| -> Result<&str, Error> {
+            Ok(field(name)?.as_str().ok_or_else(|| format!("batch_input {name} is not a string {json}"))?)
+        };
+        let [target_name] = <[String; 1]>::try_from(strings("target_name")?)
+            .map_err(|_| format!("batch_input needs exactly one target_name {json}"))?;
+        Ok(BatchInput {
+            kind: as_str("kind")?.parse()?,
+            target_name,
+            data_type: as_str("data_type")?.parse()?,
+            source_input: strings("source_input")?,
+        })
+    }
+}
+
+/// Computed batch input, to be passed to the model along with the batch
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchInputTensor {
+    pub name: String,
+    pub data_type: DataType,
+    pub shape: Vec<i64>,
+    /// Values in native byte order
+    pub data: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::{bytes, FakeRequest, FakeResponse};
+
+    fn i32s(data: &[u8]) -> Vec<i32> {
+        data.chunks_exact(4).map(|x| i32::from_ne_bytes(x.try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn test_ragged_batch() {
+        let mut fakes = [
+            FakeRequest::with_input(DataType::INT32, &[1, 3], &[1i32, 2, 3]),
+            FakeRequest::with_input(DataType::INT32, &[], &[0i32]), // no batch dimension
+            FakeRequest::with_input(DataType::INT32, &[2, 1], &[4i32, 5]),
+            FakeRequest::with_input(DataType::INT32, &[1, 0], &[0i32; 0]),
+        ];
+        let requests: Vec<Request> = fakes.iter_mut().map(|fake| fake.as_request()).collect();
+        let mut batch = RaggedBatch::<i32>::gather(&requests, "fake").unwrap();
+        assert_eq!(vec![1, 2, 3, 4, 5], batch.data);
+        assert_eq!(vec![5], batch.shape());
+        assert_eq!(Some(3..5), batch.elements(2));
+        assert_eq!(Some(5..5), batch.elements(3));
+        assert!(batch.is_failed(1));
+
+        let input = |kind| BatchInput {
+            kind,
+            target_name: "INDEX".to_string(),
+            data_type: DataType::INT32,
+            source_input: vec!["fake".to_string()],
+        };
+        let tensor = batch.batch_input(&input(BatchInputKind::ElementCount)).unwrap();
+        assert_eq!(("INDEX", vec![4]), (tensor.name.as_str(), tensor.shape));
+        assert_eq!(vec![3, 1, 1, 0], i32s(&tensor.data));
+        let tensor = batch.batch_input(&input(BatchInputKind::AccumulatedElementCount)).unwrap();
+        assert_eq!(vec![3, 4, 5, 5], i32s(&tensor.data));
+        let tensor = batch.batch_input(&input(BatchInputKind::AccumulatedElementCountWithZero)).unwrap();
+        assert_eq!((vec![5], vec![0, 3, 4, 5, 5]), (tensor.shape, i32s(&tensor.data)));
+        let tensor = batch.batch_input(&input(BatchInputKind::MaxElementCountAsShape)).unwrap();
+        assert_eq!(vec![3], tensor.shape);
+        let tensor = batch.batch_input(&input(BatchInputKind::ItemShape)).unwrap();
+        assert_eq!((vec![4, 1], vec![3, 1, 1, 0]), (tensor.shape, i32s(&tensor.data)));
+        let mut other = input(BatchInputKind::ElementCount);
+        other.source_input = vec!["other".to_string()];
+        assert!(batch.batch_input(&other).is_err());
+
+        let mut fake_responses: Vec<FakeResponse> = (0..4).map(|_| FakeResponse::default()).collect();
+        let mut responses: Vec<Option<Response>> = fake_responses.iter_mut()
+            .map(|fake| Some(Response::from_ptr(fake.as_ptr())))
+            .collect();
+        batch.send_errors(&mut responses).unwrap();
+        assert!(responses[1].is_none());
+        let output: Vec<f32> = (0..10).map(|x| x as f32).collect();
+        assert!(batch.scatter(&mut responses, "out", &[], &output).is_err());
+        batch.scatter(&mut responses, "out", &[2], &output).unwrap();
+        drop(responses);
+
+        assert_eq!(vec![1, 3, 2], fake_responses[0].outputs[0].shape);
+        assert_eq!(bytes(&[0.0f32, 1.0, 2.0, 3.0, 4.0, 5.0]), fake_responses[0].outputs[0].data);
+        assert_eq!(vec![2, 1, 2], fake_responses[2].outputs[0].shape);
+        assert_eq!(bytes(&[6.0f32, 7.0, 8.0, 9.0]), fake_responses[2].outputs[0].data);
+        assert_eq!(vec![1, 0, 2], fake_responses[3].outputs[0].shape);
+        assert!(fake_responses[1].sent.as_ref().unwrap().1.is_some());
+    }
+
+    #[test]
+    fn test_batch_input_from_model_config() {
+        let config = r#"{"name": "m", "batch_input": [
+            {"kind": "BATCH_ACCUMULATED_ELEMENT_COUNT", "target_name": ["INDEX"],
+             "data_type": "TYPE_FP32", "source_input": ["INPUT"]},
+            {"kind": "BATCH_ITEM_SHAPE", "target_name": ["SHAPE"],
+             "data_type": "TYPE_INT64", "source_input": ["INPUT"]}]}"#;
+        let inputs = BatchInput::from_model_config(config).unwrap();
+        assert_eq!(2, inputs.len());
+        assert_eq!(BatchInputKind::AccumulatedElementCount, inputs[0].kind);
+        assert_eq!("INDEX", inputs[0].target_name);
+        assert_eq!(DataType::FP32, inputs[0].data_type);
+        assert_eq!(vec!["INPUT"], inputs[0].source_input);
+        assert_eq!(DataType::INT64, inputs[1].data_type);
+
+        assert!(BatchInput::from_model_config(r#"{"name": "m"}"#).unwrap().is_empty());
+        assert!(BatchInput::from_model_config(r#"{"batch_input": [{"kind": "BATCH_ELEMENT_COUNT"}]}"#).is_err());
+        let unknown = r#"{"batch_input": [{"kind": "BATCH_FOO", "target_name": ["A"],
+            "data_type": "TYPE_INT32", "source_input": ["B"]}]}"#;
+        assert!(BatchInput::from_model_config(unknown).is_err());
+    }
+}
@@ -1,20 +1,60 @@
-use crate::{check_err, DataType, decode_string, Error, data_type::SupportedTypes};
+use crate::{check_err, BytesIter, DataType, Error, MemoryType, TransactionPolicy};
+use crate::cast::{widens_to, Cast, CastElement, Value};
+use crate::data_type::{data_type_of, SupportedTypes};
 use libc::c_void;
+use std::borrow::Cow;
 #[cfg(feature = "ndarray")]
-use ndarray::{ArrayView, IxDyn, IntoDimension};
+use ndarray::{Array, ArrayView, CowArray, Dimension, IxDyn};
 use std::ffi::CStr;
 use std::ffi::CString;
+use std::marker::PhantomData;
 use std::os::raw::c_char;
 use std::ptr;
 use std::slice;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
 pub struct Request {
     ptr: *mut triton_sys::TRITONBACKEND_Request,
+    host_policy: Option<Arc<CStr>>,
+    transaction_policy: Option<TransactionPolicy>,
+    /// Whether a response created by `Response::from_request` sent FINAL
+    pub(crate) final_sent: Arc<AtomicBool>,
+    /// Whether a response created by `Response::from_request` is alive or sent
+    pub(crate) has_response: Arc<AtomicBool>,
 }
 
 impl Request {
     pub fn from_ptr(ptr: *mut triton_sys::TRITONBACKEND_Request) -> Self {
-        Self { ptr }
+        Self {
+            ptr,
+            host_policy: None,
+            transaction_policy: None,
+            final_sent: Arc::new(AtomicBool::new(false)),
+            has_response: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// Read inputs of this request through the *ForHostPolicy variants of the
+    /// TRITONBACKEND_Input API, using the host policy of the executing instance
+    pub fn with_host_policy(mut self, host_policy: Option<Arc<CStr>>) -> Self {
+        self.host_policy = host_policy;
+        self
+    }
+
+    pub fn host_policy(&self) -> Option<&CStr> {
+        self.host_policy.as_deref()
+    }
+
+    /// Transaction policy of the model, set by `declare_backend!`
+    pub fn with_transaction_policy(mut self, policy: TransactionPolicy) -> Self {
+        self.transaction_policy = Some(policy);
+        self
+    }
+
+    pub fn transaction_policy(&self) -> Result<TransactionPolicy, Error> {
+        self.transaction_policy
+            .ok_or_else(|| "Transaction policy of the request is unknown, see Request::with_transaction_policy".into())
     }
 
     pub(crate) fn as_ptr(&self) -> *mut triton_sys::TRITONBACKEND_Request {
@@ -29,7 +69,7 @@ impl Request {
             triton_sys::TRITONBACKEND_RequestInput(self.ptr, name.as_ptr(), &mut input)
         })?;
 
-        Ok(Input::from_ptr(input))
+        Ok(Input::from_ptr(input).with_host_policy(self.host_policy.clone()))
     }
 
     pub fn get_request_id(&self
@@ -45,12 +85,22 @@ impl Request {
         }
     }
 
-    pub fn get_correlation_id(&self) -> Result<u64, Error> {
+    /// Correlation ID of the request, `CorrelationId::is_empty` if it has none
+    pub fn get_correlation_id(&self) -> Result<CorrelationId, Error> {
         let mut id: u64 = 0;
+        let err = unsafe { triton_sys::TRITONBACKEND_RequestCorrelationId(self.ptr, &mut id) };
+        if err.is_null() {
+            return Ok(CorrelationId::U64(id));
+        }
+        // fails if the ID is a string, no need to report that
+        unsafe { triton_sys::TRITONSERVER_ErrorDelete(err) };
+
+        let mut id_ptr: *const c_char = ptr::null();
         check_err(unsafe {
-            triton_sys::TRITONBACKEND_RequestCorrelationId(self.ptr, &mut id)
+            triton_sys::TRITONBACKEND_RequestCorrelationIdString(self.ptr, &mut id_ptr)
         })?;
-        Ok(id)
+        let id = unsafe { CStr::from_ptr(id_ptr) }.to_string_lossy().into_owned();
+        Ok(CorrelationId::String(id))
     }
 
     pub fn get_flags(&self) -> Result<RequestFlags, Error> {
@@ -61,6 +111,16 @@ impl Request {
         Ok(RequestFlags::from(flags))
     }
 
+    /// Whether the client cancelled the request or disconnected
+    #[cfg(feature = "cancellation")]
+    pub fn is_cancelled(&self) -> Result<bool, Error> {
+        let mut cancelled = false;
+        check_err(unsafe {
+            triton_sys::TRITONBACKEND_RequestIsCancelled(self.ptr, &mut cancelled)
+        })?;
+        Ok(cancelled)
+    }
+
     // Request (or any of it's inputs) must NOT be used after release
     pub fn release(&self, flags: RequestReleaseFlags) -> Result<(), Error> {
         check_err(unsafe {
@@ -71,75 +131,282 @@ impl Request {
 
 pub struct Input {
     ptr: *mut triton_sys::TRITONBACKEND_Input,
+    host_policy: Option<Arc<CStr>>,
 }
-impl<'a> Input {
+impl Input {
     pub fn from_ptr(ptr: *mut triton_sys::TRITONBACKEND_Input) -> Self {
-        Self { ptr }
+        Self { ptr, host_policy: None }
+    }
+
+    /// Make `properties`, `slice` and `buffer` use the given host policy
+    pub fn with_host_policy(mut self, host_policy: Option<Arc<CStr>>) -> Self {
+        self.host_policy = host_policy;
+        self
     }
 
+    /// Data of the first input buffer as slice. Refuses buffers not in CPU memory
     pub fn slice<T>(&self) -> Result<&[T], Error> {
+        self.buffer(0, MemoryType::CPU)?.as_slice()
+    }
+
+    /// Like `slice`, but for the input buffer placed according to `host_policy`
+    pub fn slice_for_host_policy<T>(&self, host_policy: &str) -> Result<&[T], Error> {
+        self.buffer_for_host_policy(host_policy, 0, MemoryType::CPU)?.as_slice()
+    }
+
+    /// Buffer `index` (of `buffer_count`) holding (part of) the tensor data.
+    /// `memory_type` is only a preference, check the returned `memory_type`.
+    pub fn buffer(&self, index: u32, memory_type: MemoryType) -> Result<InputBuffer<'_>, Error> {
+        self.buffer_impl(self.host_policy.as_deref(), index, memory_type)
+    }
+
+    /// Like `buffer`, but placed according to `host_policy`
+    pub fn buffer_for_host_policy(&self, host_policy: &str, index: u32, memory_type: MemoryType)
+            -> Result<InputBuffer<'_>, Error> {
+        let host_policy = CString::new(host_policy)?;
+        self.buffer_impl(Some(&host_policy), index, memory_type)
+    }
+
+    fn buffer_impl(&self, host_policy: Option<&CStr>, index: u32, memory_type: MemoryType)
+            -> Result<InputBuffer<'_>, Error> {
         let mut buffer: *const c_void = ptr::null_mut();
-        let index = 0;
-        let mut memory_type = triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_CPU;
+        let mut memory_type = memory_type as u32;
         let mut memory_type_id = 0;
         let mut buffer_byte_size = 0;
         check_err(unsafe {
-            triton_sys::TRITONBACKEND_InputBuffer(
-                self.ptr,
-                index,
-                &mut buffer,
-                &mut buffer_byte_size,
-                &mut memory_type,
-                &mut memory_type_id,
-            )
+            match host_policy {
+                Some(host_policy) => triton_sys::TRITONBACKEND_InputBufferForHostPolicy(
+                    self.ptr,
+                    host_policy.as_ptr(),
+                    index,
+                    &mut buffer,
+                    &mut buffer_byte_size,
+                    &mut memory_type,
+                    &mut memory_type_id,
+                ),
+                None => triton_sys::TRITONBACKEND_InputBuffer(
+                    self.ptr,
+                    index,
+                    &mut buffer,
+                    &mut buffer_byte_size,
+                    &mut memory_type,
+                    &mut memory_type_id,
+                ),
+            }
         })?;
 
-        let element_len = buffer_byte_size as usize / std::mem::size_of::<T>();
-
-        let mem: &[T] =
-            unsafe { slice::from_raw_parts(buffer as *mut T, element_len) };
-        Ok(mem)
+        Ok(InputBuffer {
+            data: buffer,
+            byte_size: buffer_byte_size,
+            memory_type: memory_type.try_into()?,
+            memory_type_id,
+            _input: PhantomData,
+        })
     }
 
+    /// First element of a BYTES tensor, invalid UTF-8 replaced lossily
     pub fn as_string(&self) -> Result<String, Error> {
+        let Some(bytes) = self.as_bytes_iter()?.next() else {
+            return Err("BYTES tensor is empty".into());
+        };
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// All elements of a BYTES tensor, failing on invalid UTF-8
+    pub fn as_strings(&self) -> Result<Vec<String>, Error> {
+        self.as_bytes_iter()?
+            .map(|bytes| Ok(std::str::from_utf8(&bytes)?.to_owned()))
+            .collect()
+    }
+
+    /// All elements of a BYTES tensor, invalid UTF-8 replaced lossily
+    pub fn as_strings_lossy(&self) -> Result<Vec<String>, Error> {
+        let strings = self.as_bytes_iter()?
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+            .collect();
+        Ok(strings)
+    }
+
+    /// Iterator over the elements of a BYTES tensor, zero-copy unless the
+    /// tensor spans several buffers. The number of elements is checked against
+    /// the shape.
+    pub fn as_bytes_iter(&self) -> Result<BytesIter<'_>, Error> {
         let properties = self.properties()?;
         if properties.datatype != DataType::BYTES {
             return Err(format!("DataType does not match String {properties:?}").into());
         }
-        let buffer = self.slice::<u8>()?;
-        let strings = decode_string(buffer)?;
-        Ok(strings.first().unwrap().clone())
+        let element_count = properties.element_count()?;
+        let buffer = self.contiguous_bytes(properties.buffer_count)?;
+        BytesIter::new(buffer, Some(element_count))
     }
 
     pub fn as_u64(&self) -> Result<u64, Error> {
+        self.scalar_widening::<u64>()
+    }
+
+    /// Value of a 1-element tensor, its DataType must match T
+    pub fn scalar<T: CastElement>(&self) -> Result<T, Error> {
+        self.scalar_impl(false)
+    }
+
+    /// Like `scalar`, also accepting DataTypes that widen to T without loss
+    /// (e.g. INT32 for i64, FP16 for f32)
+    pub fn scalar_widening<T: CastElement>(&self) -> Result<T, Error> {
+        self.scalar_impl(true)
+    }
+
+    /// Copy of the tensor data, its DataType must match T
+    pub fn to_vec<T: CastElement>(&self) -> Result<Vec<T>, Error> {
+        self.to_vec_impl(false)
+    }
+
+    /// Like `to_vec`, also accepting DataTypes that widen to T without loss
+    pub fn to_vec_widening<T: CastElement>(&self) -> Result<Vec<T>, Error> {
+        self.to_vec_impl(true)
+    }
+
+    fn scalar_impl<T: CastElement>(&self, widen: bool) -> Result<T, Error> {
+        match self.to_vec_impl(widen)?[..] {
+            [value] => Ok(value),
+            ref values => Err(format!("Expected scalar, got {} elements", values.len()).into()),
+        }
+    }
+
+    fn to_vec_impl<T: CastElement>(&self, widen: bool) -> Result<Vec<T>, Error> {
         let properties = self.properties()?;
-        if properties.datatype != DataType::UINT64 {
-            return Err(format!("DataType does not match u64 {properties:?}").into());
+        let (from, to) = (properties.datatype, data_type_of::<T>()?);
+        if from != to && !(widen && widens_to(from, to)) {
+            return Err(format!("DataType does not match {to} {properties:?}").into());
         }
-        if properties.byte_size < 8 {
-            return Err("Buffer too small".into())
+        let data = self.element_bytes(&properties)?;
+        convert(from, &data, T::from_value)
+    }
+
+    /// Tensor data as T, borrowed when the DataType matches, otherwise each
+    /// element is converted according to `cast`
+    pub fn cast_to<T: CastElement>(&self, cast: Cast) -> Result<Cow<'_, [T]>, Error> {
+        let properties = self.properties()?;
+        let (from, to) = (properties.datatype, data_type_of::<T>()?);
+        let data = self.element_bytes(&properties)?;
+        if from == to {
+            if let Cow::Borrowed(data) = data {
+                if let Some(data) = crate::reinterpret::<T>(data) {
+                    return Ok(Cow::Borrowed(data));
+                }
+            }
         }
-        let buffer = self.slice::<u8>()?;
-        let mut bytes = [0u8; 8];
-        bytes.copy_from_slice(buffer);
-        Ok(u64::from_le_bytes(bytes))
+        convert(from, &data, |value| T::cast_from(value, cast)).map(Cow::Owned)
     }
 
+    /// Like `cast_to`, as ndarray
     #[cfg(feature="ndarray")]
-    pub fn as_array<T, const N: usize>(&'a self) -> Result<ArrayView<'a, T, IxDyn>, Error>
-            where T: SupportedTypes {
+    pub fn as_array_cast<T, const N: usize>(&self, cast: Cast) -> Result<CowArray<'_, T, IxDyn>, Error>
+            where T: CastElement {
         let properties = self.properties()?;
-        assert!(<T as SupportedTypes>::of() == properties.datatype);
-        assert!(N == properties.shape.len());
-        const { assert!( N < 6) };
-        let shape: Vec<usize> = properties.shape.iter().map(|&x| x as usize).collect();
-        let shape = shape.into_dimension();
-        let data = self.slice::<T>()?;
-        let array: ArrayView<T, IxDyn> = ArrayView::from_shape(shape, data)?;
+        if N != properties.shape.len() {
+            return Err(format!("Expected {N} dimensions {properties:?}").into());
+        }
+        let shape = crate::array_shape::<IxDyn>(&properties.shape)?;
+        let array = match self.cast_to::<T>(cast)? {
+            Cow::Borrowed(data) => ArrayView::from_shape(shape, data)?.into(),
+            Cow::Owned(data) => Array::from_shape_vec(shape, data)?.into(),
+        };
         Ok(array)
     }
 
+    /// Tensor data, checked to hold exactly the elements in shape
+    fn element_bytes(&self, properties: &InputProperties) -> Result<Cow<'_, [u8]>, Error> {
+        let element_count = properties.element_count()?;
+        let byte_size = properties.datatype.byte_size() as usize;
+        if byte_size == 0 {
+            return Err(format!("DataType has no fixed element size {properties:?}").into());
+        }
+        let data = self.contiguous_bytes(properties.buffer_count)?;
+        if data.len() != element_count * byte_size {
+            return Err(format!("Expected {element_count} elements, got {} bytes {properties:?}",
+                               data.len()).into());
+        }
+        Ok(data)
+    }
+
+    /// Data from all input buffers, only copied when spread over several
+    pub(crate) fn contiguous_bytes(&self, buffer_count: u32) -> Result<Cow<'_, [u8]>, Error> {
+        match buffer_count {
+            0 => Ok(Cow::Borrowed(&[])),
+            1 => Ok(Cow::Borrowed(self.slice::<u8>()?)),
+            _ => {
+                let mut data = vec![];
+                for index in 0..buffer_count {
+                    data.extend_from_slice(self.buffer(index, MemoryType::CPU)?.as_slice::<u8>()?);
+                }
+                Ok(Cow::Owned(data))
+            },
+        }
+    }
+
+    /// FP16, BF16 or FP32 input, converted to f32
+    #[cfg(feature = "half")]
+    pub fn to_f32_vec(&self) -> Result<Vec<f32>, Error> {
+        let properties = self.properties()?;
+        if !matches!(properties.datatype, DataType::FP16 | DataType::BF16 | DataType::FP32) {
+            return Err(format!("DataType does not match f32 {properties:?}").into());
+        }
+        let data = self.element_bytes(&properties)?;
+        convert(properties.datatype, &data, f32::from_value)
+    }
+
+    /// Tensor data as ndarray of any rank, which must be N
+    #[cfg(feature="ndarray")]
+    pub fn as_array<T, const N: usize>(&self) -> Result<ArrayView<'_, T, IxDyn>, Error>
+            where T: SupportedTypes {
+        let array = self.as_array_dim::<T, IxDyn>()?;
+        if array.ndim() != N {
+            return Err(format!("Expected {N} dimensions, got shape {:?}", array.shape()).into());
+        }
+        Ok(array)
+    }
+
+    /// Tensor data as ndarray of fixed dimension, e.g. `Ix2` for `ArrayView2`
+    #[cfg(feature="ndarray")]
+    pub fn as_array_dim<T, D>(&self) -> Result<ArrayView<'_, T, D>, Error>
+            where T: SupportedTypes, D: Dimension {
+        let properties = self.properties()?;
+        let data_type = data_type_of::<T>()?;
+        if data_type != properties.datatype {
+            return Err(format!("DataType does not match {data_type} {properties:?}").into());
+        }
+        let shape = crate::array_shape::<D>(&properties.shape)?;
+        Ok(ArrayView::from_shape(shape, self.slice::<T>()?)?)
+    }
+
+    /// Tensor as DLPack, borrowing the single input buffer. `memory_type` is
+    /// only a preference, check the device of the returned tensor.
+    #[cfg(feature = "dlpack")]
+    pub fn to_dlpack(&self, memory_type: MemoryType) -> Result<crate::dlpack::DLPackTensor<'_>, Error> {
+        let properties = self.properties()?;
+        let (data, device) = match properties.buffer_count {
+            0 => (ptr::null(), crate::dlpack::DLDevice::new(memory_type, 0)),
+            1 => {
+                let buffer = self.buffer(0, memory_type)?;
+                (buffer.data, crate::dlpack::DLDevice::new(buffer.memory_type, buffer.memory_type_id))
+            },
+            _ => return Err(format!("DLPack of multiple buffers not supported {properties:?}").into()),
+        };
+        crate::dlpack::DLPackTensor::new(data, device, properties.datatype, &properties.shape)
+    }
+
     pub fn properties(&self) -> Result<InputProperties, Error> {
+        self.properties_impl(self.host_policy.as_deref())
+    }
+
+    /// Like `properties`, but `byte_size` and `buffer_count` describe the input
+    /// buffers placed according to `host_policy`
+    pub fn properties_for_host_policy(&self, host_policy: &str) -> Result<InputProperties, Error> {
+        let host_policy = CString::new(host_policy)?;
+        self.properties_impl(Some(&host_policy))
+    }
+
+    fn properties_impl(&self, host_policy: Option<&CStr>) -> Result<InputProperties, Error> {
         let mut name = ptr::null();
         let mut datatype = 0u32;
         let mut shape = ptr::null();
@@ -148,15 +415,27 @@ impl<'a> Input {
         let mut buffer_count = 0u32;
 
         check_err(unsafe {
-            triton_sys::TRITONBACKEND_InputProperties(
-                self.ptr,
-                &mut name,
-                &mut datatype,
-                &mut shape,
-                &mut dims_count,
-                &mut byte_size,
-                &mut buffer_count,
-            )
+            match host_policy {
+                Some(host_policy) => triton_sys::TRITONBACKEND_InputPropertiesForHostPolicy(
+                    self.ptr,
+                    host_policy.as_ptr(),
+                    &mut name,
+                    &mut datatype,
+                    &mut shape,
+                    &mut dims_count,
+                    &mut byte_size,
+                    &mut buffer_count,
+                ),
+                None => triton_sys::TRITONBACKEND_InputProperties(
+                    self.ptr,
+                    &mut name,
+                    &mut datatype,
+                    &mut shape,
+                    &mut dims_count,
+                    &mut byte_size,
+                    &mut buffer_count,
+                ),
+            }
         })?;
 
         let name: &CStr = unsafe { CStr::from_ptr(name) };
@@ -177,6 +456,45 @@ impl<'a> Input {
 }
 
 
+/// Buffer holding (part of) the data of an Input, owned by that Input
+#[derive(Debug)]
+pub struct InputBuffer<'a> {
+    pub data: *const c_void,
+    pub byte_size: u64,
+    pub memory_type: MemoryType,
+    pub memory_type_id: i64,
+    _input: PhantomData<&'a Input>,
+}
+
+impl<'a> InputBuffer<'a> {
+    /// View buffer as slice, refusing memory that the host cannot dereference
+    pub fn as_slice<T>(&self) -> Result<&'a [T], Error> {
+        self.memory_type.check_cpu("Input buffer")?;
+        if self.data.is_null() || self.byte_size == 0 {
+            return Ok(&[]);
+        }
+
+        let element_len = self.byte_size as usize / std::mem::size_of::<T>();
+
+        let mem: &[T] =
+            unsafe { slice::from_raw_parts(self.data as *const T, element_len) };
+        Ok(mem)
+    }
+}
+
+/// Convert each `from` element in `data` into T
+fn convert<T>(from: DataType, data: &[u8], convert: impl Fn(Value) -> Option<T>)
+        -> Result<Vec<T>, Error> where T: SupportedTypes {
+    data.chunks_exact(from.byte_size() as usize)
+        .map(|bytes| {
+            let value = Value::read(from, bytes)?;
+            convert(value).ok_or_else(|| {
+                format!("Cannot convert {value:?} to {}", <T as SupportedTypes>::of()).into()
+            })
+        })
+        .collect()
+}
+
 #[derive(Debug)]
 pub struct InputProperties {
     pub name: String,
@@ -186,6 +504,57 @@ pub struct InputProperties {
     pub buffer_count: u32,
 }
 
+impl InputProperties {
+    /// Number of elements according to shape
+    pub fn element_count(&self) -> Result<usize, Error> {
+        crate::element_count(&self.shape)
+    }
+}
+
+/// Correlation ID of a sequence, Triton accepts unsigned integers and strings
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CorrelationId {
+    U64(u64),
+    String(String),
+}
+
+impl CorrelationId {
+    /// Whether this is 0 or "", i.e. no correlation ID
+    pub fn is_empty(&self) -> bool {
+        match self {
+            CorrelationId::U64(id) => *id == 0,
+            CorrelationId::String(id) => id.is_empty(),
+        }
+    }
+}
+
+impl std::fmt::Display for CorrelationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CorrelationId::U64(id) => write!(f, "{id}"),
+            CorrelationId::String(id) => write!(f, "{id:?}"),
+        }
+    }
+}
+
+impl From<u64> for CorrelationId {
+    fn from(id: u64) -> CorrelationId {
+        CorrelationId::U64(id)
+    }
+}
+
+impl From<String> for CorrelationId {
+    fn from(id: String) -> CorrelationId {
+        CorrelationId::String(id)
+    }
+}
+
+impl From<&str> for CorrelationId {
+    fn from(id: &str) -> CorrelationId {
+        CorrelationId::String(id.to_string())
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct RequestFlags(u32);
 
@@ -214,3 +583,243 @@ pub enum RequestReleaseFlags {
 //  RESCHEDULE = triton_sys::tritonserver_requestreleaseflag_enum_TRITONSERVER_REQUEST_RELEASE_RESCHEDULE,
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::{bytes, FakeInput, FakeRequest};
+
+    #[test]
+    fn test_get_correlation_id() {
+        let mut fake = FakeRequest::new(vec![]);
+        fake.correlation_id = 7;
+        assert_eq!(CorrelationId::U64(7), fake.as_request().get_correlation_id().unwrap());
+        fake.correlation_id_string = Some(c"session-1".to_owned());
+        let id = fake.as_request().get_correlation_id().unwrap();
+        assert_eq!(CorrelationId::from("session-1"), id);
+        assert_eq!("\"session-1\"", id.to_string());
+        assert!(!id.is_empty());
+        fake.correlation_id_string = Some(c"".to_owned());
+        assert!(fake.as_request().get_correlation_id().unwrap().is_empty());
+    }
+
+    #[cfg(feature = "cancellation")]
+    #[test]
+    fn test_is_cancelled() {
+        let mut fake = FakeRequest::new(vec![]);
+        assert!(!fake.as_request().is_cancelled().unwrap());
+        fake.cancelled = true;
+        assert!(fake.as_request().is_cancelled().unwrap());
+    }
+
+    #[test]
+    fn test_slice_memory_type() {
+        let data = 1.5f32.to_le_bytes().to_vec();
+        let mut fake = FakeInput::new(DataType::FP32, &[1], vec![(data, MemoryType::CPU)]);
+        let input = fake.as_input();
+        let buffer = input.buffer(0, MemoryType::CPU).unwrap();
+        assert_eq!(MemoryType::CPU, buffer.memory_type);
+        assert_eq!(4, buffer.byte_size);
+        assert_eq!(&[1.5f32], input.slice::<f32>().unwrap());
+
+        let data = 2.5f32.to_le_bytes().to_vec();
+        let mut fake = FakeInput::new(DataType::FP32, &[1], vec![(data, MemoryType::CPU_PINNED)]);
+        let input = fake.as_input();
+        assert_eq!(&[2.5f32], input.slice::<f32>().unwrap());
+
+        let data = 3.5f32.to_le_bytes().to_vec();
+        let mut fake = FakeInput::new(DataType::FP32, &[1], vec![(data, MemoryType::GPU)]);
+        let input = fake.as_input();
+        let buffer = input.buffer(0, MemoryType::CPU).unwrap();
+        assert_eq!(MemoryType::GPU, buffer.memory_type);
+        let err = input.slice::<f32>().unwrap_err();
+        assert_eq!("Input buffer is in GPU memory, expected CPU", err.to_string());
+        assert!(input.slice_for_host_policy::<f32>("numa0").is_err());
+    }
+
+    #[test]
+    fn test_host_policy() {
+        let mut fake = FakeInput::new(DataType::FP32, &[1], vec![(bytes(&[1.5f32]), MemoryType::CPU)]);
+        fake.host_policy_buffers.push((c"numa1".into(), vec![(bytes(&[2.5f32]), MemoryType::CPU)]));
+        let input = fake.as_input();
+        assert_eq!(&[1.5f32], input.slice::<f32>().unwrap());
+        assert_eq!(&[2.5f32], input.slice_for_host_policy::<f32>("numa1").unwrap());
+        assert_eq!(&[1.5f32], input.slice_for_host_policy::<f32>("numa0").unwrap());
+
+        let mut fake = FakeRequest::new(vec![fake]);
+        let request = fake.as_request().with_host_policy(Some(c"numa1".into()));
+        let input = request.get_input("fake").unwrap();
+        assert_eq!(&[2.5f32], input.slice::<f32>().unwrap());
+        assert_eq!(1, input.properties().unwrap().buffer_count);
+    }
+
+    #[test]
+    fn test_as_strings() {
+        let mut data = crate::encode_string("foo");
+        data.extend(crate::encode_string("bar"));
+        let mut fake = FakeInput::new(DataType::BYTES, &[1, 2], vec![(data.clone(), MemoryType::CPU)]);
+        let input = fake.as_input();
+        assert_eq!(vec!["foo", "bar"], input.as_strings().unwrap());
+        assert_eq!("foo", input.as_string().unwrap());
+        assert_eq!(2, input.as_bytes_iter().unwrap().len());
+
+        // elements spread over several buffers
+        let buffers = vec![(data[..5].to_vec(), MemoryType::CPU), (data[5..].to_vec(), MemoryType::CPU)];
+        let mut fake = FakeInput::new(DataType::BYTES, &[2], buffers);
+        assert_eq!(vec!["foo", "bar"], fake.as_input().as_strings().unwrap());
+        assert_eq!(2, fake.as_input().as_bytes_iter().unwrap().len());
+
+        // element count does not match shape
+        let mut fake = FakeInput::new(DataType::BYTES, &[3], vec![(data.clone(), MemoryType::CPU)]);
+        assert!(fake.as_input().as_strings().is_err());
+
+        // empty tensor
+        let mut fake = FakeInput::new(DataType::BYTES, &[0], vec![]);
+        assert!(fake.as_input().as_strings().unwrap().is_empty());
+        assert!(fake.as_input().as_string().is_err());
+
+        // truncated
+        data.pop();
+        let mut fake = FakeInput::new(DataType::BYTES, &[2], vec![(data, MemoryType::CPU)]);
+        assert!(fake.as_input().as_strings_lossy().is_err());
+
+        // invalid UTF-8
+        let data = vec![2, 0, 0, 0, b'a', 0xff];
+        let mut fake = FakeInput::new(DataType::BYTES, &[1], vec![(data, MemoryType::CPU)]);
+        assert!(fake.as_input().as_strings().is_err());
+        assert_eq!(vec!["a\u{FFFD}"], fake.as_input().as_strings_lossy().unwrap());
+    }
+
+    #[test]
+    fn test_scalar() {
+        let data = 42i32.to_ne_bytes().to_vec();
+        let mut fake = FakeInput::new(DataType::INT32, &[1], vec![(data, MemoryType::CPU)]);
+        let input = fake.as_input();
+        assert_eq!(42, input.scalar::<i32>().unwrap());
+        assert!(input.scalar::<i64>().is_err());
+        assert_eq!(42, input.scalar_widening::<i64>().unwrap());
+        assert_eq!(42.0, input.scalar_widening::<f64>().unwrap());
+        assert!(input.scalar_widening::<f32>().is_err());
+        assert!(input.scalar_widening::<u64>().is_err());
+
+        // UINT64 in buffer larger than one element
+        let data = [7u64.to_ne_bytes(), 8u64.to_ne_bytes()].concat();
+        let mut fake = FakeInput::new(DataType::UINT64, &[1, 2], vec![(data, MemoryType::CPU)]);
+        let input = fake.as_input();
+        assert!(input.as_u64().is_err());
+        assert_eq!(vec![7, 8], input.to_vec::<u64>().unwrap());
+
+        // data spread over buffers
+        let buffers = vec![(vec![1u8, 2], MemoryType::CPU), (vec![3], MemoryType::CPU_PINNED)];
+        let mut fake = FakeInput::new(DataType::UINT8, &[3], buffers);
+        assert_eq!(vec![1u16, 2, 3], fake.as_input().to_vec_widening::<u16>().unwrap());
+
+        // byte size does not match shape
+        let mut fake = FakeInput::new(DataType::UINT8, &[4], vec![(vec![1, 2, 3], MemoryType::CPU)]);
+        assert!(fake.as_input().to_vec::<u8>().is_err());
+    }
+
+    #[test]
+    fn test_cast_to() {
+        let data = bytes(&[1i64, -2, 300]);
+        let mut fake = FakeInput::new(DataType::INT64, &[3], vec![(data, MemoryType::CPU)]);
+        let input = fake.as_input();
+        let same = input.cast_to::<i64>(Cast::Checked).unwrap();
+        assert!(matches!(same, Cow::Borrowed(&[1, -2, 300])));
+        assert_eq!(vec![1i32, -2, 300], *input.cast_to::<i32>(Cast::Checked).unwrap());
+        assert_eq!(vec![1.0f64, -2.0, 300.0], *input.cast_to::<f64>(Cast::Checked).unwrap());
+        assert!(input.cast_to::<i8>(Cast::Checked).is_err());
+        assert_eq!(vec![1i8, -2, 127], *input.cast_to::<i8>(Cast::Saturating).unwrap());
+        assert_eq!(vec![1u8, 0, 255], *input.cast_to::<u8>(Cast::Saturating).unwrap());
+
+        // BOOL only borrowed when valid
+        let mut fake = FakeInput::new(DataType::BOOL, &[2], vec![(vec![1, 0], MemoryType::CPU)]);
+        let valid = fake.as_input();
+        assert!(matches!(valid.cast_to::<bool>(Cast::Checked).unwrap(), Cow::Borrowed(&[true, false])));
+        let mut fake = FakeInput::new(DataType::BOOL, &[2], vec![(vec![2, 0], MemoryType::CPU)]);
+        let invalid = fake.as_input();
+        assert!(matches!(invalid.cast_to::<bool>(Cast::Checked).unwrap(), Cow::Owned(_)));
+    }
+
+    #[test]
+    #[cfg(feature = "dlpack")]
+    fn test_to_dlpack() {
+        let data = bytes(&[1.5f32, 2.5, 3.5]);
+        let mut fake = FakeInput::new(DataType::FP32, &[1, 3], vec![(data, MemoryType::CPU_PINNED)]);
+        let input = fake.as_input();
+        let dlpack = input.to_dlpack(MemoryType::CPU).unwrap();
+        let tensor = dlpack.tensor();
+        assert_eq!(2, tensor.ndim);
+        assert_eq!(&[1, 3], unsafe { std::slice::from_raw_parts(tensor.shape, 2) });
+        assert_eq!(DataType::FP32, tensor.dtype.try_into().unwrap());
+        assert_eq!(crate::dlpack::DL_CUDA_HOST, tensor.device.device_type);
+        assert_eq!(input.buffer(0, MemoryType::CPU).unwrap().data, tensor.data as *const _);
+        let bytes = unsafe { crate::dlpack::read_tensor(tensor) }.unwrap().data;
+        assert_eq!(fake.buffers[0].0, *bytes);
+
+        let mut fake = FakeInput::new(DataType::BYTES, &[1], vec![(vec![0; 4], MemoryType::CPU)]);
+        assert!(fake.as_input().to_dlpack(MemoryType::CPU).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn test_as_array() {
+        let data = bytes(&(0..24).collect::<Vec<i16>>());
+        let mut fake = FakeInput::new(DataType::INT16, &[2, 3, 4], vec![(data, MemoryType::CPU)]);
+        let input = fake.as_input();
+        let array = input.as_array::<i16, 3>().unwrap();
+        assert_eq!(&[2, 3, 4], array.shape());
+        assert_eq!(23, array[[1, 2, 3]]);
+        assert!(input.as_array::<i16, 2>().is_err());
+        assert!(input.as_array::<u16, 3>().is_err());
+        let array = input.as_array_dim::<i16, ndarray::Ix3>().unwrap();
+        assert_eq!(13, array[(1, 0, 1)]);
+        assert!(input.as_array_dim::<i16, ndarray::Ix2>().is_err());
+
+        let data = bytes(&(0..8).collect::<Vec<i16>>());
+        let mut fake = FakeInput::new(DataType::INT16, &[1, 1, 1, 1, 2, 2, 2], vec![(data, MemoryType::CPU)]);
+        assert_eq!(7, fake.as_input().as_array::<i16, 7>().unwrap().ndim());
+        let mut fake = FakeInput::new(DataType::INT16, &[-1], vec![]);
+        assert!(fake.as_input().as_array::<i16, 1>().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn test_as_array_cast() {
+        let data = bytes(&[1.5f64, 2.0, -3.0, 4.25]);
+        let mut fake = FakeInput::new(DataType::FP64, &[2, 2], vec![(data, MemoryType::CPU)]);
+        let input = fake.as_input();
+        let array = input.as_array_cast::<f32, 2>(Cast::Checked).unwrap();
+        assert_eq!(&[2, 2], array.shape());
+        assert_eq!(-3.0, array[[1, 0]]);
+        assert!(input.as_array_cast::<i32, 2>(Cast::Checked).is_err());
+        let array = input.as_array_cast::<i32, 2>(Cast::Saturating).unwrap();
+        assert_eq!(vec![1, 2, -3, 4], array.iter().copied().collect::<Vec<_>>());
+        assert!(input.as_array_cast::<f64, 2>(Cast::Checked).unwrap().is_view());
+        assert!(input.as_array_cast::<f64, 1>(Cast::Checked).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "half")]
+    fn test_to_f32_vec() {
+        let data = crate::stub::bytes(&[half::f16::from_f32(1.5), half::f16::from_f32(-2.0)]);
+        let mut fake = FakeInput::new(DataType::FP16, &[2], vec![(data, MemoryType::CPU)]);
+        assert_eq!(vec![1.5, -2.0], fake.as_input().to_f32_vec().unwrap());
+
+        let data = crate::stub::bytes(&[half::bf16::from_f32(0.25)]);
+        let mut fake = FakeInput::new(DataType::BF16, &[1], vec![(data, MemoryType::CPU)]);
+        assert_eq!(vec![0.25], fake.as_input().to_f32_vec().unwrap());
+
+        let buffers = vec![
+            (crate::stub::bytes(&[half::f16::from_f32(1.0)]), MemoryType::CPU),
+            (crate::stub::bytes(&[half::f16::from_f32(2.0), half::f16::from_f32(3.0)]), MemoryType::CPU),
+        ];
+        let mut fake = FakeInput::new(DataType::FP16, &[3], buffers.clone());
+        assert_eq!(vec![1.0, 2.0, 3.0], fake.as_input().to_f32_vec().unwrap());
+        let mut fake = FakeInput::new(DataType::FP16, &[4], buffers);
+        assert!(fake.as_input().to_f32_vec().is_err());
+
+        let mut fake = FakeInput::new(DataType::INT32, &[1], vec![(vec![0; 4], MemoryType::CPU)]);
+        assert!(fake.as_input().to_f32_vec().is_err());
+    }
+}
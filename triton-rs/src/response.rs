@@ -1,13 +1,16 @@
 use crate::{check_err, Error};
-use crate::{DataType, Request};
-use crate::data_type::SupportedTypes;
+use crate::{CancellationToken, DataType, MemoryType, ParameterValue, Request, TransactionPolicy};
+use crate::data_type::{data_type_of, SupportedTypes};
 use libc::c_void;
 #[cfg(feature = "ndarray")]
-use ndarray::{Array, IxDyn};
+use ndarray::{ArrayBase, ArrayViewMut, Data, Dimension, IxDyn};
 use std::ffi::CString;
 use std::mem;
+use std::ops::Deref;
 use std::ptr;
 use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
 #[repr(u32)]
 #[derive(Clone, Copy, Debug)]
@@ -18,19 +21,39 @@ pub enum ResponseFlags {
 
 pub struct Response {
    ptr: *mut triton_sys::TRITONBACKEND_Response,
+   /// Whether the request or factory this response was created from has sent FINAL
+   final_sent: Option<Arc<AtomicBool>>,
+   /// `Request::has_response` of the request this response was created from
+   has_response: Option<Arc<AtomicBool>>,
 }
 
 impl Response {
-    fn from_ptr(ptr: *mut triton_sys::TRITONBACKEND_Response) -> Self {
-        Self { ptr }
+    pub(crate) fn from_ptr(ptr: *mut triton_sys::TRITONBACKEND_Response) -> Self {
+        Self { ptr, final_sent: None, has_response: None }
     }
 
+    /// Response of `request`, which must be its only one, see `Responder`
     pub fn from_request(request: &Request) -> Result<Self, Error> {
+        if request.final_sent.load(Ordering::SeqCst) {
+            return Err("Request already has a response, decoupled models send more \
+                        through a ResponseFactory".into());
+        }
+        if request.has_response.swap(true, Ordering::SeqCst) {
+            return Err("Request already has a response or a decoupled Responder, \
+                        send or drop the response before creating another".into());
+        }
         let mut response: *mut triton_sys::TRITONBACKEND_Response = ptr::null_mut();
-        check_err(unsafe {
+        let created = check_err(unsafe {
             triton_sys::TRITONBACKEND_ResponseNew(&mut response, request.as_ptr())
-        })?;
-        Ok(Response::from_ptr(response))
+        });
+        if let Err(err) = created {
+            request.has_response.store(false, Ordering::SeqCst);
+            return Err(err);
+        }
+        let mut response = Response::from_ptr(response);
+        response.final_sent = Some(request.final_sent.clone());
+        response.has_response = Some(request.has_response.clone());
+        Ok(response)
     }
 
     pub fn from_factory(factory: &ResponseFactory) -> Result<Self, Error> {
@@ -38,10 +61,16 @@ impl Response {
         check_err(unsafe {
             triton_sys::TRITONBACKEND_ResponseNewFromFactory(&mut response, factory.as_ptr())
         })?;
-        Ok(Response::from_ptr(response))
+        let mut response = Response::from_ptr(response);
+        response.final_sent = Some(factory.final_sent.clone());
+        Ok(response)
     }
 
-    pub fn send(self, flags: ResponseFlags, error: Option<Error>) -> Result<(), Error> {
+    pub fn send(mut self, flags: ResponseFlags, error: Option<Error>) -> Result<(), Error> {
+        let final_sent = self.final_sent.take();
+        if let Some(final_sent) = &final_sent {
+            check_not_final(final_sent)?;
+        }
         let error = match error {
             Some(error) => unsafe {
                 let error_code = triton_sys::TRITONSERVER_errorcode_enum_TRITONSERVER_ERROR_UNSUPPORTED;
@@ -54,9 +83,31 @@ impl Response {
             triton_sys::TRITONBACKEND_ResponseSend(self.ptr, flags as u32, error)
         })?;
         mem::forget(self); // prevent Drop because, send frees Response
+        if let (ResponseFlags::FINAL, Some(final_sent)) = (flags, final_sent) {
+            final_sent.store(true, Ordering::SeqCst);
+        }
         Ok(())
     }
 
+    /// Attach parameter `name`, a string, i64 or bool, e.g. `("finish_reason", "stop")`
+    pub fn set_parameter(&mut self, name: &str, value: impl Into<ParameterValue>) -> Result<(), Error> {
+        let name = CString::new(name)?;
+        check_err(match value.into() {
+            ParameterValue::String(value) => {
+                let value = CString::new(value)?;
+                unsafe {
+                    triton_sys::TRITONBACKEND_ResponseSetStringParameter(self.ptr, name.as_ptr(), value.as_ptr())
+                }
+            },
+            ParameterValue::Int(value) => unsafe {
+                triton_sys::TRITONBACKEND_ResponseSetIntParameter(self.ptr, name.as_ptr(), value)
+            },
+            ParameterValue::Bool(value) => unsafe {
+                triton_sys::TRITONBACKEND_ResponseSetBoolParameter(self.ptr, name.as_ptr(), value)
+            },
+        })
+    }
+
     fn output(&mut self, name: &str, data_type: DataType, shape: &[i64]) -> Result<Output, Error> {
         let name = CString::new(name)?;
         let mut output: *mut triton_sys::TRITONBACKEND_Output = ptr::null_mut();
@@ -76,27 +127,141 @@ impl Response {
 
     pub fn add_output<T>(&mut self, name: &str, shape: &[i64], data: &[T]) -> Result<(), Error>
     where T: Copy + SupportedTypes {
-        let data_type = <T as SupportedTypes>::of();
-        assert_eq!(data_type.byte_size() as usize, std::mem::size_of::<T>());
+        let data_type = data_type_of::<T>()?;
         let mut output = self.output(name, data_type, shape)?;
-        if data.len() > 0 {
+        if !data.is_empty() {
             output.set_data(data)?;
         }
         Ok(())
     }
 
+    /// Add output and borrow its buffer, zero filled, to compute the result in
+    /// place. The buffer belongs to Triton and is sent along with the response.
+    pub fn output_mut<T>(&mut self, name: &str, shape: &[i64]) -> Result<&mut [T], Error>
+    where T: Copy + SupportedTypes {
+        let data_type = data_type_of::<T>()?;
+        if data_type == DataType::BYTES {
+            return Err(format!("Output {name} is BYTES, use add_output_strings").into());
+        }
+        let element_count = crate::element_count(shape)?;
+        let mut output = self.output(name, data_type, shape)?;
+        if element_count == 0 {
+            return Ok(&mut []);
+        }
+        let buffer = output.buffer(element_count * mem::size_of::<T>())?;
+        buffer.fill(0); // all zero bits are valid for every SupportedTypes
+        // SAFETY: T is a plain numeric type of the checked size
+        match unsafe { buffer.align_to_mut::<T>() } {
+            ([], data, []) => Ok(data),
+            _ => Err(format!("Output {name} buffer is not aligned for {data_type}").into()),
+        }
+    }
+
+    /// Like `output_mut`, as ndarray
     #[cfg(feature = "ndarray")]
-    pub fn add_output_array<T>(&mut self, name: &str, array: Array<T, IxDyn>) -> Result<(), Error>
+    pub fn output_array_mut<T>(&mut self, name: &str, shape: &[i64]) -> Result<ArrayViewMut<'_, T, IxDyn>, Error>
     where T: Copy + SupportedTypes {
+        // output_mut rejects negative dims
+        let dims: Vec<usize> = shape.iter().map(|x| *x as usize).collect();
+        let data = self.output_mut(name, shape)?;
+        Ok(ArrayViewMut::from_shape(IxDyn(&dims), data)?)
+    }
+
+    /// Add output copied from a DLPack tensor in host memory, in row major order
+    ///
+    /// # Safety
+    /// `tensor` must describe valid memory
+    #[cfg(feature = "dlpack")]
+    pub unsafe fn add_output_dlpack(&mut self, name: &str, tensor: &crate::dlpack::DLTensor) -> Result<(), Error> {
+        let host = unsafe { crate::dlpack::read_tensor(tensor)? };
+        let mut output = self.output(name, host.data_type, host.shape)?;
+        if !host.data.is_empty() {
+            output.buffer(host.data.len())?.copy_from_slice(&host.data);
+        }
+        Ok(())
+    }
+
+    /// Add BYTES output, each element length-prefix encoded straight into the
+    /// output buffer. The number of elements must match `shape`.
+    pub fn add_output_strings<I>(&mut self, name: &str, shape: &[i64], elements: I) -> Result<(), Error>
+    where I: IntoIterator, I::Item: AsRef<[u8]> {
+        let elements: Vec<I::Item> = elements.into_iter().collect();
+        let element_count = crate::element_count(shape)?;
+        if elements.len() != element_count {
+            return Err(format!("Output {name} has {} elements, but shape {shape:?} needs {element_count}",
+                               elements.len()).into());
+        }
+        let byte_size = elements.iter().map(|e| crate::encoded_len(e.as_ref())).sum();
+        let mut output = self.output(name, DataType::BYTES, shape)?;
+        if byte_size > 0 {
+            let mut buffer = output.buffer(byte_size)?;
+            for element in &elements {
+                let written = crate::encode_bytes_into(element.as_ref(), buffer);
+                buffer = &mut buffer[written..];
+            }
+        }
+        Ok(())
+    }
+
+    /// Add FP16 or BF16 output, converted from f32 straight into the output buffer
+    #[cfg(feature = "half")]
+    pub fn add_output_from_f32(&mut self, name: &str, data_type: DataType, shape: &[i64], data: &[f32])
+            -> Result<(), Error> {
+        let convert: fn(f32) -> u16 = match data_type {
+            DataType::FP16 => |x| half::f16::from_f32(x).to_bits(),
+            DataType::BF16 => |x| half::bf16::from_f32(x).to_bits(),
+            _ => return Err(format!("Cannot convert f32 to {data_type:?}").into()),
+        };
+        let mut output = self.output(name, data_type, shape)?;
+        if !data.is_empty() {
+            let buffer = output.buffer(data.len() * std::mem::size_of::<u16>())?;
+            for (bytes, &x) in buffer.chunks_exact_mut(2).zip(data) {
+                bytes.copy_from_slice(&convert(x).to_ne_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "ndarray")]
+    pub fn add_output_string_array<A, S, D>(&mut self, name: &str, array: ArrayBase<S, D>) -> Result<(), Error>
+    where A: AsRef<[u8]>, S: Data<Elem = A>, D: Dimension {
         let shape: Vec<i64> = array.shape().iter().map(|x| *x as i64).collect();
-        let data = array.as_raw_ref();
-        let data = unsafe { slice::from_raw_parts(data.as_ptr(), data.len()) };
-        self.add_output(name, &shape, data)
+        self.add_output_strings(name, &shape, array.iter())
+    }
+
+    /// Add output from an array or view of any rank and memory layout, written
+    /// in standard (row major) order
+    #[cfg(feature = "ndarray")]
+    pub fn add_output_array<T, S, D>(&mut self, name: &str, array: ArrayBase<S, D>) -> Result<(), Error>
+    where T: Copy + SupportedTypes, S: Data<Elem = T>, D: Dimension {
+        let shape: Vec<i64> = array.shape().iter().map(|x| *x as i64).collect();
+        match array.as_slice() {
+            Some(data) => self.add_output(name, &shape, data),
+            None => {
+                let buffer = self.output_mut::<T>(name, &shape)?;
+                for (x, &y) in buffer.iter_mut().zip(array.iter()) {
+                    *x = y;
+                }
+                Ok(())
+            },
+        }
     }
 }
 
+// SAFETY: Triton does not tie a TRITONBACKEND_Response to a thread: the
+// backend API lets responses be created, filled and sent from any thread, e.g.
+// after model_instance_execute returned, it only requires that one response is
+// not used concurrently. A Response owns its handle exclusively, it is neither
+// Clone nor Sync and output buffers borrow it mutably, so moving it to another
+// thread cannot introduce concurrent use. `final_sent` is an Arc<AtomicBool>.
+unsafe impl Send for Response {}
+
 impl Drop for Response {
     fn drop(&mut self) {
+        // not sent, the request may get another response
+        if let Some(has_response) = &self.has_response {
+            has_response.store(false, Ordering::SeqCst);
+        }
         let error = unsafe {
             triton_sys::TRITONBACKEND_ResponseDelete(self.ptr)
         };
@@ -114,9 +279,23 @@ impl Output {
     }
 
     pub fn set_data<T: Copy>(&mut self, data: &[T]) -> Result<(), Error> {
-        let mut buffer: *mut c_void = ptr::null_mut();
         let element_len = data.len();
-        let buffer_byte_size = std::mem::size_of_val(data) as u64;
+        let buffer = self.buffer(std::mem::size_of_val(data))?;
+
+        let mem: &mut [T] = unsafe {
+            slice::from_raw_parts_mut(buffer.as_mut_ptr() as *mut T, element_len)
+        };
+
+        mem.copy_from_slice(data);
+
+        Ok(())
+    }
+
+    /// Allocate output buffer of `byte_size` bytes in CPU memory. The buffer is
+    /// owned by the Response, callers must not let 'r outlive it.
+    fn buffer<'r>(&mut self, byte_size: usize) -> Result<&'r mut [u8], Error> {
+        let mut buffer: *mut c_void = ptr::null_mut();
+        let buffer_byte_size = byte_size as u64;
         let mut memory_type = triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_CPU;
         let mut memory_type_id = 0;
         check_err(unsafe {
@@ -132,24 +311,25 @@ impl Output {
         if buffer.is_null() {
             return Err("Failed to allocate output buffer".into());
         }
+        MemoryType::try_from(memory_type)?.check_cpu("Output buffer")?;
 
-        let mem: &mut [T] = unsafe {
-            slice::from_raw_parts_mut(buffer as *mut T, element_len)
+        let mem: &mut [u8] = unsafe {
+            slice::from_raw_parts_mut(buffer as *mut u8, byte_size)
         };
-
-        mem.copy_from_slice(data);
-
-        Ok(())
+        Ok(mem)
     }
 }
 
 pub struct ResponseFactory {
    ptr: *mut triton_sys::TRITONBACKEND_ResponseFactory,
+   final_sent: Arc<AtomicBool>,
+   cancel: CancellationToken,
 }
 
 impl ResponseFactory {
-    fn from_ptr(ptr: *mut triton_sys::TRITONBACKEND_ResponseFactory) -> Self {
-        Self { ptr }
+    pub(crate) fn from_ptr(ptr: *mut triton_sys::TRITONBACKEND_ResponseFactory) -> Self {
+        let cancel = CancellationToken::for_factory(ptr);
+        Self { ptr, final_sent: Arc::new(AtomicBool::new(false)), cancel }
     }
 
     pub(crate) fn as_ptr(&self) -> *mut triton_sys::TRITONBACKEND_ResponseFactory {
@@ -165,15 +345,84 @@ impl ResponseFactory {
     }
 
     pub fn send_flags(&self, flags: ResponseFlags) -> Result<(), Error> {
+        check_not_final(&self.final_sent)?;
         check_err(unsafe {
             triton_sys::TRITONBACKEND_ResponseFactorySendFlags(self.ptr, flags as u32)
         })?;
+        if let ResponseFlags::FINAL = flags {
+            self.final_sent.store(true, Ordering::SeqCst);
+        }
         Ok(())
     }
+
+    /// Whether FINAL was sent, by `send_flags` or a response of this factory
+    pub fn is_final_sent(&self) -> bool {
+        self.final_sent.load(Ordering::SeqCst)
+    }
+
+    /// Whether the client cancelled the request or disconnected
+    #[cfg(feature = "cancellation")]
+    pub fn is_cancelled(&self) -> Result<bool, Error> {
+        crate::cancel::factory_is_cancelled(self.ptr)
+    }
+
+    /// Token to poll for cancellation, also after the factory moved to another thread
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+}
+
+/// Nothing may be sent after FINAL, Triton drops it. Debug builds fail instead,
+/// to point out the bug in the backend.
+fn check_not_final(final_sent: &AtomicBool) -> Result<(), Error> {
+    if cfg!(debug_assertions) && final_sent.load(Ordering::SeqCst) {
+        return Err("Response sent after the FINAL flag".into());
+    }
+    Ok(())
+}
+
+/// ResponseFactory that sends FINAL with an error when dropped before FINAL
+/// was sent, e.g. on an early return or panic, so the client does not wait
+/// forever
+pub struct FinalGuard {
+    factory: ResponseFactory,
+}
+
+impl FinalGuard {
+    pub fn new(factory: ResponseFactory) -> Self {
+        Self { factory }
+    }
+
+    pub fn from_request(request: &Request) -> Result<Self, Error> {
+        Ok(Self::new(ResponseFactory::from_request(request)?))
+    }
+}
+
+impl Deref for FinalGuard {
+    type Target = ResponseFactory;
+
+    fn deref(&self) -> &ResponseFactory {
+        &self.factory
+    }
+}
+
+impl Drop for FinalGuard {
+    fn drop(&mut self) {
+        if self.factory.is_final_sent() {
+            return;
+        }
+        let error = "Response stream ended without the FINAL flag".into();
+        let sent = Response::from_factory(&self.factory)
+            .and_then(|response| response.send(ResponseFlags::FINAL, Some(error)));
+        if sent.is_err() {
+            let _ = self.factory.send_flags(ResponseFlags::FINAL);
+        }
+    }
 }
 
 impl Drop for ResponseFactory {
     fn drop(&mut self) {
+        self.cancel.detach();
         let error = unsafe {
             triton_sys::TRITONBACKEND_ResponseFactoryDelete(self.ptr)
         };
@@ -181,3 +430,409 @@ impl Drop for ResponseFactory {
     }
 }
 
+/// Sends the responses of one request as the transaction policy of the model
+/// requires: a single response with FINAL for one-to-one models, any number of
+/// responses followed by FINAL for decoupled models. The policy comes with
+/// the request, see `Request::transaction_policy`.
+pub struct Responder<'r> {
+    request: &'r Request,
+    /// Response stream of decoupled models
+    stream: Option<FinalGuard>,
+}
+
+impl<'r> Responder<'r> {
+    /// Fails if the request already has a response from `Response::from_request`.
+    /// For decoupled models the responder then takes over the request, so
+    /// that `Response::from_request` fails.
+    pub fn new(request: &'r Request) -> Result<Self, Error> {
+        let policy = request.transaction_policy()?;
+        let claimed = match policy {
+            TransactionPolicy::OneToOne => request.has_response.load(Ordering::SeqCst),
+            TransactionPolicy::Decoupled => request.has_response.swap(true, Ordering::SeqCst),
+        };
+        if claimed || request.final_sent.load(Ordering::SeqCst) {
+            return Err("Request already has a response or a decoupled Responder".into());
+        }
+        let stream = match policy {
+            TransactionPolicy::OneToOne => None,
+            TransactionPolicy::Decoupled => match FinalGuard::from_request(request) {
+                Ok(stream) => Some(stream),
+                Err(err) => {
+                    request.has_response.store(false, Ordering::SeqCst);
+                    return Err(err);
+                },
+            },
+        };
+        Ok(Self { request, stream })
+    }
+
+    pub fn is_decoupled(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// New response to fill with outputs, then pass to `send`. Only one for
+    /// one-to-one models, another fails until it is dropped unsent.
+    pub fn response(&self) -> Result<Response, Error> {
+        match &self.stream {
+            Some(stream) => Response::from_factory(stream),
+            None => Response::from_request(self.request),
+        }
+    }
+
+    /// Send a response, the final one for one-to-one models
+    pub fn send(&self, response: Response) -> Result<(), Error> {
+        match &self.stream {
+            Some(_) => response.send(ResponseFlags::NONE, None),
+            None if self.request.final_sent.load(Ordering::SeqCst) => Err(NOT_DECOUPLED.into()),
+            None => response.send(ResponseFlags::FINAL, None),
+        }
+    }
+
+    /// Send an error response, ending the responses of the request
+    pub fn send_error(self, error: Error) -> Result<(), Error> {
+        let flags = ResponseFlags::FINAL;
+        match &self.stream {
+            Some(stream) => Response::from_factory(stream)?.send(flags, Some(error)),
+            None if self.request.final_sent.load(Ordering::SeqCst) => {
+                Err(format!("{NOT_DECOUPLED}, cannot also send error: {error}").into())
+            },
+            None => Response::from_request(self.request)?.send(flags, Some(error)),
+        }
+    }
+
+    /// Send FINAL, for one-to-one models the response must have been sent
+    pub fn finish(self) -> Result<(), Error> {
+        match &self.stream {
+            Some(stream) => stream.send_flags(ResponseFlags::FINAL),
+            None if self.request.final_sent.load(Ordering::SeqCst) => Ok(()),
+            None => Err("Model is not decoupled, it must send a response for each request".into()),
+        }
+    }
+
+    /// Keep sending from another thread or task, decoupled models only
+    pub fn into_sender(mut self) -> Result<ResponseSender, Error> {
+        match self.stream.take() {
+            Some(stream) => Ok(ResponseSender { factory: stream }),
+            None => Err("Model is not decoupled, its responses cannot be streamed".into()),
+        }
+    }
+}
+
+const NOT_DECOUPLED: &str = "Model is not decoupled, it sends one response per request";
+
+/// Decoupled response stream of one request, which can be moved to a worker
+/// thread or task to keep sending after `model_instance_execute` returned.
+///
+/// Triton allows creating and sending responses through a response factory
+/// from any thread, and keeps it valid after the request is released, until
+/// the factory is deleted. The stream ends with `finish` or `send_error`, or
+/// with an error response when the sender is dropped before, see FinalGuard.
+pub struct ResponseSender {
+    factory: FinalGuard,
+}
+
+// SAFETY: see above, the factory is owned by the sender and only used through it
+unsafe impl Send for ResponseSender {}
+
+impl ResponseSender {
+    pub fn new(factory: ResponseFactory) -> Self {
+        Self { factory: FinalGuard::new(factory) }
+    }
+
+    /// Sender for `request` of a decoupled model, create it before the
+    /// request is released
+    pub fn from_request(request: &Request) -> Result<Self, Error> {
+        if !request.transaction_policy()?.is_decoupled() {
+            return Err("Model is not decoupled, its responses cannot be streamed".into());
+        }
+        Ok(Self::new(ResponseFactory::from_request(request)?))
+    }
+
+    /// New response to fill with outputs, then pass to `send`
+    pub fn response(&self) -> Result<Response, Error> {
+        Response::from_factory(&self.factory)
+    }
+
+    /// Send a response of the stream, more may follow
+    pub fn send(&self, response: Response) -> Result<(), Error> {
+        response.send(ResponseFlags::NONE, None)
+    }
+
+    /// End the stream with an error response
+    pub fn send_error(self, error: Error) -> Result<(), Error> {
+        self.response()?.send(ResponseFlags::FINAL, Some(error))
+    }
+
+    /// Whether the stream was cancelled, cheap enough to poll per response
+    pub fn is_cancelled(&self) -> bool {
+        self.factory.cancel.is_cancelled()
+    }
+
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.factory.cancellation_token()
+    }
+
+    /// End the stream, without another response
+    pub fn finish(self) -> Result<(), Error> {
+        self.factory.send_flags(ResponseFlags::FINAL)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stub::{bytes, FakeRequest, FakeResponse, FakeResponseFactory};
+
+    #[test]
+    fn test_add_output_strings() {
+        let mut fake = FakeResponse::default();
+        let mut response = Response::from_ptr(fake.as_ptr());
+        response.add_output_strings("text", &[2], ["foo", "bär"]).unwrap();
+        response.add_output_strings("empty", &[0, 3], Vec::<String>::new()).unwrap();
+        assert!(response.add_output_strings("bad", &[3], ["foo", "bar"]).is_err());
+        drop(response);
+
+        let output = &fake.outputs[0];
+        assert_eq!("text", output.name.to_str().unwrap());
+        assert_eq!(DataType::BYTES, output.datatype);
+        assert_eq!(vec![2], output.shape);
+        assert_eq!(vec!["foo", "bär"], crate::decode_string(&output.data).unwrap());
+        assert_eq!(vec![0, 3], fake.outputs[1].shape);
+        assert!(fake.outputs[1].data.is_empty());
+    }
+
+    #[test]
+    fn test_set_parameter() {
+        let mut fake = FakeResponse::default();
+        let mut response = Response::from_ptr(fake.as_ptr());
+        response.set_parameter("finish_reason", "stop").unwrap();
+        response.set_parameter("completion_tokens", 17).unwrap();
+        response.set_parameter("cached", true).unwrap();
+        assert!(response.set_parameter("bad\0name", 1).is_err());
+        drop(response);
+
+        assert_eq!(vec![
+            ("finish_reason".to_string(), ParameterValue::from("stop")),
+            ("completion_tokens".to_string(), ParameterValue::Int(17)),
+            ("cached".to_string(), ParameterValue::Bool(true)),
+        ], fake.parameters);
+    }
+
+    #[test]
+    fn test_output_mut() {
+        let mut fake = FakeResponse::default();
+        let mut response = Response::from_ptr(fake.as_ptr());
+        let data = response.output_mut::<i32>("ints", &[2, 2]).unwrap();
+        assert_eq!(&[0; 4], data);
+        data.copy_from_slice(&[1, -2, 3, -4]);
+        assert!(response.output_mut::<i32>("empty", &[0]).unwrap().is_empty());
+        assert!(response.output_mut::<crate::RawBytes>("bytes", &[1]).is_err());
+        assert!(response.output_mut::<f32>("negative", &[-1]).is_err());
+        drop(response);
+
+        assert_eq!(DataType::INT32, fake.outputs[0].datatype);
+        assert_eq!(vec![2, 2], fake.outputs[0].shape);
+        assert_eq!(bytes(&[1i32, -2, 3, -4]), fake.outputs[0].data);
+        assert_eq!(2, fake.outputs.len());
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn test_output_array_mut() {
+        let mut fake = FakeResponse::default();
+        let mut response = Response::from_ptr(fake.as_ptr());
+        let mut array = response.output_array_mut::<f32>("floats", &[2, 3]).unwrap();
+        array.index_axis_mut(ndarray::Axis(0), 1).fill(2.5);
+        array[[0, 2]] = -1.0;
+        drop(response);
+
+        assert_eq!(vec![2, 3], fake.outputs[0].shape);
+        assert_eq!(bytes(&[0.0f32, 0.0, -1.0, 2.5, 2.5, 2.5]), fake.outputs[0].data);
+        assert!(Response::from_ptr(fake.as_ptr()).output_array_mut::<f32>("negative", &[-1, 2]).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "ndarray")]
+    fn test_add_output_array() {
+        let array = ndarray::Array::from_shape_vec((2, 3), vec![1u32, 2, 3, 4, 5, 6]).unwrap();
+        let mut fake = FakeResponse::default();
+        let mut response = Response::from_ptr(fake.as_ptr());
+        response.add_output_array("standard", array.view()).unwrap();
+        response.add_output_array("transposed", array.t()).unwrap();
+        response.add_output_array("sliced", array.slice(ndarray::s![.., 1..;2])).unwrap();
+        response.add_output_array("owned", array.into_dyn()).unwrap();
+        drop(response);
+
+        assert_eq!(bytes(&[1u32, 2, 3, 4, 5, 6]), fake.outputs[0].data);
+        assert_eq!(vec![3, 2], fake.outputs[1].shape);
+        assert_eq!(bytes(&[1u32, 4, 2, 5, 3, 6]), fake.outputs[1].data);
+        assert_eq!(vec![2, 1], fake.outputs[2].shape);
+        assert_eq!(bytes(&[2u32, 5]), fake.outputs[2].data);
+        assert_eq!(bytes(&[1u32, 2, 3, 4, 5, 6]), fake.outputs[3].data);
+    }
+
+    #[test]
+    #[cfg(feature = "dlpack")]
+    fn test_add_output_dlpack() {
+        use crate::dlpack::{DLDevice, DLTensor, DL_CPU};
+        let data = [1i8, 2, 3, 4, 5, 6];
+        let mut shape = [2i64, 3];
+        let mut strides = [1i64, 2]; // column major
+        let tensor = DLTensor {
+            data: data.as_ptr() as *mut _,
+            device: DLDevice { device_type: DL_CPU, device_id: 0 },
+            ndim: 2,
+            dtype: DataType::INT8.try_into().unwrap(),
+            shape: shape.as_mut_ptr(),
+            strides: strides.as_mut_ptr(),
+            byte_offset: 0,
+        };
+        let mut fake = FakeResponse::default();
+        let mut response = Response::from_ptr(fake.as_ptr());
+        unsafe { response.add_output_dlpack("out", &tensor) }.unwrap();
+        drop(response);
+
+        assert_eq!(DataType::INT8, fake.outputs[0].datatype);
+        assert_eq!(vec![2, 3], fake.outputs[0].shape);
+        assert_eq!(vec![1, 3, 5, 2, 4, 6], fake.outputs[0].data);
+    }
+
+    #[test]
+    #[cfg(feature = "half")]
+    fn test_add_output_from_f32() {
+        let mut fake = FakeResponse::default();
+        let mut response = Response::from_ptr(fake.as_ptr());
+        response.add_output_from_f32("fp16", DataType::FP16, &[2], &[1.5, -2.0]).unwrap();
+        response.add_output_from_f32("bf16", DataType::BF16, &[1], &[0.25]).unwrap();
+        assert!(response.add_output_from_f32("int", DataType::INT16, &[1], &[0.0]).is_err());
+        drop(response);
+
+        assert_eq!(DataType::FP16, fake.outputs[0].datatype);
+        assert_eq!(crate::stub::bytes(&[half::f16::from_f32(1.5), half::f16::from_f32(-2.0)]), fake.outputs[0].data);
+        assert_eq!(DataType::BF16, fake.outputs[1].datatype);
+        assert_eq!(half::bf16::from_f32(0.25).to_ne_bytes().to_vec(), fake.outputs[1].data);
+        assert_eq!(2, fake.outputs.len());
+    }
+
+    #[test]
+    fn test_response_sender() {
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        std::thread::spawn(move || {
+            for step in 0..2 {
+                let mut response = sender.response().unwrap();
+                response.add_output("step", &[1], &[step]).unwrap();
+                sender.send(response).unwrap();
+            }
+            sender.finish().unwrap();
+        }).join().unwrap();
+
+        assert_eq!(2, fake.responses.len());
+        assert_eq!(Some((ResponseFlags::NONE as u32, None)), fake.responses[1].sent);
+        assert_eq!(1i32.to_ne_bytes().to_vec(), fake.responses[1].outputs[0].data);
+        assert_eq!(vec![ResponseFlags::FINAL as u32], fake.flags);
+        assert!(fake.deleted);
+
+        let mut fake = FakeResponseFactory::default();
+        let sender = fake.as_sender();
+        sender.send_error("out of memory".into()).unwrap();
+        let sent = fake.responses[0].sent.clone().unwrap();
+        assert_eq!((ResponseFlags::FINAL as u32, Some("out of memory".to_string())), sent);
+        assert!(fake.flags.is_empty() && fake.deleted);
+    }
+
+    #[test]
+    fn test_final_guard() {
+        let mut fake = FakeResponseFactory::default();
+        let guard = FinalGuard::new(ResponseFactory::from_ptr(fake.as_ptr()));
+        Response::from_factory(&guard).unwrap().send(ResponseFlags::NONE, None).unwrap();
+        drop(guard); // e.g. early return
+        let (flags, error) = fake.responses[1].sent.clone().unwrap();
+        assert_eq!(ResponseFlags::FINAL as u32, flags);
+        assert!(error.unwrap().contains("without the FINAL flag"));
+        assert!(fake.deleted);
+
+        let mut fake = FakeResponseFactory::default();
+        let guard = FinalGuard::new(ResponseFactory::from_ptr(fake.as_ptr()));
+        let response = Response::from_factory(&guard).unwrap();
+        Response::from_factory(&guard).unwrap().send(ResponseFlags::FINAL, None).unwrap();
+        assert!(guard.is_final_sent());
+        if cfg!(debug_assertions) {
+            assert!(response.send(ResponseFlags::NONE, None).is_err());
+            assert!(guard.send_flags(ResponseFlags::FINAL).is_err());
+        }
+        drop(guard);
+        assert!(fake.responses[0].sent.is_none() && fake.flags.is_empty());
+
+        let mut fake = FakeResponseFactory::default();
+        let guard = FinalGuard::new(ResponseFactory::from_ptr(fake.as_ptr()));
+        guard.send_flags(ResponseFlags::FINAL).unwrap();
+        drop(guard);
+        assert!(fake.responses.is_empty());
+    }
+
+    #[test]
+    fn test_responder() {
+        let mut fake = FakeRequest::new(vec![]);
+        let request = fake.as_request().with_transaction_policy(TransactionPolicy::OneToOne);
+        let responder = Responder::new(&request).unwrap();
+        assert!(!responder.is_decoupled());
+        let response = responder.response().unwrap();
+        assert!(responder.response().is_err());
+        assert!(Response::from_request(&request).is_err());
+        responder.send(response).unwrap();
+        let err = responder.response().err().unwrap();
+        assert!(err.to_string().contains("already has a response"));
+        assert!(Response::from_request(&request).is_err());
+        assert!(responder.finish().is_ok());
+        assert!(Responder::new(&request).is_err());
+        drop(request);
+        assert_eq!(1, fake.responses.len());
+        assert_eq!(Some((ResponseFlags::FINAL as u32, None)), fake.responses[0].sent);
+
+        let mut fake = FakeRequest::new(vec![]);
+        let request = fake.as_request().with_transaction_policy(TransactionPolicy::OneToOne);
+        let responder = Responder::new(&request).unwrap();
+        drop(responder.response().unwrap()); // dropped unsent, another may follow
+        assert!(responder.into_sender().is_err());
+        assert!(Responder::new(&request).unwrap().finish().is_err());
+        Responder::new(&request).unwrap().send_error("bad input".into()).unwrap();
+        drop(request);
+        assert_eq!(Some((ResponseFlags::FINAL as u32, Some("bad input".to_string()))), fake.responses[1].sent);
+
+        let mut fake = FakeRequest::new(vec![]);
+        let request = fake.as_request();
+        assert!(Responder::new(&request).is_err()); // policy unknown
+        let request = request.with_transaction_policy(TransactionPolicy::OneToOne);
+        let response = Response::from_request(&request).unwrap();
+        assert!(Responder::new(&request).is_err());
+        drop(response);
+
+        let mut fake = FakeRequest::new(vec![]);
+        let request = fake.as_request().with_transaction_policy(TransactionPolicy::Decoupled);
+        let responder = Responder::new(&request).unwrap();
+        assert!(responder.is_decoupled());
+        assert!(Response::from_request(&request).is_err());
+        assert!(Responder::new(&request).is_err());
+        responder.send(responder.response().unwrap()).unwrap();
+        responder.send(responder.response().unwrap()).unwrap();
+        responder.finish().unwrap();
+        drop(request);
+        assert_eq!(2, fake.factory.responses.len());
+        assert_eq!(Some((ResponseFlags::NONE as u32, None)), fake.factory.responses[1].sent);
+        assert_eq!(vec![ResponseFlags::FINAL as u32], fake.factory.flags);
+        assert!(fake.responses.is_empty() && fake.factory.deleted);
+    }
+
+    #[test]
+    fn test_response_sender_from_request() {
+        let mut fake = FakeRequest::new(vec![]);
+        let request = fake.as_request().with_transaction_policy(TransactionPolicy::OneToOne);
+        assert!(ResponseSender::from_request(&request).is_err());
+        let request = request.with_transaction_policy(TransactionPolicy::Decoupled);
+        ResponseSender::from_request(&request).unwrap().finish().unwrap();
+        drop(request);
+        assert_eq!(vec![ResponseFlags::FINAL as u32], fake.factory.flags);
+    }
+}
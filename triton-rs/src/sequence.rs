@@ -0,0 +1,483 @@
+//! Per-sequence state for `sequence_batching` models, keyed by correlation ID,
+//! and the control tensors the sequence batcher adds to their requests
+
+use crate::{CorrelationId, DataType, Error, Request, RequestFlags, Response, ResponseFlags};
+use crate::request::Input;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::time::{Duration, Instant};
+
+/// Triton's default for `max_sequence_idle_microseconds`
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// State of all active sequences, to keep in the model instance state
+pub struct SequenceStore<S> {
+    sequences: HashMap<CorrelationId, Entry<S>>,
+    idle_timeout: Option<Duration>,
+}
+
+struct Entry<S> {
+    state: S,
+    last_used: Instant,
+}
+
+impl<S> SequenceStore<S> {
+    /// Store evicting sequences idle for longer than `idle_timeout`, if any
+    pub fn new(idle_timeout: Option<Duration>) -> Self {
+        Self { sequences: HashMap::new(), idle_timeout }
+    }
+
+    /// Store with the idle timeout of `sequence_batching` in the model config,
+    /// as JSON from `Model::model_config`
+    pub fn from_model_config(model_config: &str) -> Result<Self, Error> {
+        let config: serde_json::Value = serde_json::from_str(model_config)?;
+        let Some(sequence_batching) = config.get("sequence_batching") else {
+            return Err("Model config has no sequence_batching".into());
+        };
+        // uint64 fields are strings in the JSON form of the config
+        let idle_timeout = match sequence_batching.get("max_sequence_idle_microseconds") {
+            None => DEFAULT_IDLE_TIMEOUT,
+            Some(serde_json::Value::String(micros)) => Duration::from_micros(micros.parse()?),
+            Some(micros) => Duration::from_micros(micros.as_u64().ok_or_else(|| {
+                format!("Invalid max_sequence_idle_microseconds {micros}")
+            })?),
+        };
+        Ok(Self::new(Some(idle_timeout).filter(|timeout| !timeout.is_zero())))
+    }
+
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    /// State of the sequence `request` belongs to: created with `init` on
+    /// START, looked up for continuing requests, and removed once the returned
+    /// Sequence of an END request is dropped. Fails for unknown sequences.
+    pub fn get(&mut self, request: &Request, init: impl FnOnce() -> S) -> Result<Sequence<'_, S>, Error> {
+        let correlation_id = request.get_correlation_id()?;
+        let flags = request.get_flags()?;
+        self.get_at(correlation_id, flags, Instant::now(), init)
+    }
+
+    /// Like `get`, but sends an error response for requests of unknown
+    /// sequences, returning None. The request must not be used for another
+    /// response then.
+    pub fn get_or_reject(&mut self, request: &Request, init: impl FnOnce() -> S)
+            -> Result<Option<Sequence<'_, S>>, Error> {
+        let correlation_id = request.get_correlation_id()?;
+        let flags = request.get_flags()?;
+        let now = Instant::now();
+        if let Err(err) = self.check(&correlation_id, flags, now) {
+            Response::from_request(request)?.send(ResponseFlags::FINAL, Some(err))?;
+            return Ok(None);
+        }
+        Ok(Some(self.open(correlation_id, flags, now, init)))
+    }
+
+    fn check(&mut self, correlation_id: &CorrelationId, flags: RequestFlags, now: Instant) -> Result<(), Error> {
+        if correlation_id.is_empty() {
+            return Err("Sequence request without correlation ID".into());
+        }
+        self.evict_idle_at(now);
+        if !flags.is_start() && !self.sequences.contains_key(correlation_id) {
+            return Err(format!("Unknown sequence with correlation ID {correlation_id}").into());
+        }
+        Ok(())
+    }
+
+    fn get_at(&mut self, correlation_id: CorrelationId, flags: RequestFlags, now: Instant, init: impl FnOnce() -> S)
+            -> Result<Sequence<'_, S>, Error> {
+        self.check(&correlation_id, flags, now)?;
+        Ok(self.open(correlation_id, flags, now, init))
+    }
+
+    /// Sequence of a request that passed `check`
+    fn open(&mut self, correlation_id: CorrelationId, flags: RequestFlags, now: Instant, init: impl FnOnce() -> S)
+            -> Sequence<'_, S> {
+        if flags.is_start() {
+            // a correlation ID may be reused once its sequence ended
+            self.sequences.insert(correlation_id.clone(), Entry { state: init(), last_used: now });
+        }
+        self.sequences.get_mut(&correlation_id).unwrap().last_used = now;
+        Sequence { store: self, correlation_id, end: flags.is_end() }
+    }
+
+    /// Remove sequences idle for longer than the idle timeout, returning them
+    pub fn evict_idle(&mut self) -> Vec<(CorrelationId, S)> {
+        self.evict_idle_at(Instant::now())
+    }
+
+    fn evict_idle_at(&mut self, now: Instant) -> Vec<(CorrelationId, S)> {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return vec![];
+        };
+        let idle: Vec<CorrelationId> = self.sequences.iter()
+            .filter(|(_, entry)| now.duration_since(entry.last_used) > idle_timeout)
+            .map(|(correlation_id, _)| correlation_id.clone())
+            .collect();
+        idle.into_iter()
+            .filter_map(|id| self.sequences.remove(&id).map(|entry| (id, entry.state)))
+            .collect()
+    }
+
+    /// Remove the state of a sequence
+    pub fn remove(&mut self, correlation_id: &CorrelationId) -> Option<S> {
+        self.sequences.remove(correlation_id).map(|entry| entry.state)
+    }
+}
+
+/// State of one sequence, removed from the store when dropped after the END
+/// request
+pub struct Sequence<'a, S> {
+    store: &'a mut SequenceStore<S>,
+    correlation_id: CorrelationId,
+    end: bool,
+}
+
+impl<S> Sequence<'_, S> {
+    pub fn correlation_id(&self) -> &CorrelationId {
+        &self.correlation_id
+    }
+
+    /// Whether this is the last request of the sequence
+    pub fn is_end(&self) -> bool {
+        self.end
+    }
+}
+
+impl<S> Deref for Sequence<'_, S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.store.sequences[&self.correlation_id].state
+    }
+}
+
+impl<S> DerefMut for Sequence<'_, S> {
+    fn deref_mut(&mut self) -> &mut S {
+        &mut self.store.sequences.get_mut(&self.correlation_id).unwrap().state
+    }
+}
+
+impl<S> Drop for Sequence<'_, S> {
+    fn drop(&mut self) {
+        if self.end {
+            self.store.sequences.remove(&self.correlation_id);
+        }
+    }
+}
+
+/// Kind of a sequence control, see `control_input` of `sequence_batching`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ControlKind {
+    Start,
+    End,
+    Ready,
+    CorrelationId,
+}
+
+impl std::str::FromStr for ControlKind {
+    type Err = Error;
+
+    fn from_str(kind: &str) -> Result<ControlKind, Error> {
+        let kind = match kind {
+            "CONTROL_SEQUENCE_START" => Self::Start,
+            "CONTROL_SEQUENCE_END" => Self::End,
+            "CONTROL_SEQUENCE_READY" => Self::Ready,
+            "CONTROL_SEQUENCE_CORRID" => Self::CorrelationId,
+            _ => return Err(format!("Unknown control kind {kind:?}").into()),
+        };
+        Ok(kind)
+    }
+}
+
+/// Tensor values meaning false and true
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FalseTrue {
+    Int32([i32; 2]),
+    Fp32([f32; 2]),
+    Bool([bool; 2]),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Control {
+    input_name: String,
+    /// None for CORRID, its value is passed as is
+    false_true: Option<FalseTrue>,
+    data_type: DataType,
+}
+
+/// Names and encodings of the control inputs in the model config, for the
+/// Oldest strategy and other backends that read controls as tensors
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SequenceControls {
+    start: Option<Control>,
+    end: Option<Control>,
+    ready: Option<Control>,
+    corrid: Option<Control>,
+}
+
+/// Controls of one request. Unlike `RequestFlags`, these are also set for
+/// requests the sequence batcher fills in, e.g. READY false for empty slots.
+/// A field is None if the model config has no such control.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SequenceControl {
+    pub start: Option<bool>,
+    pub end: Option<bool>,
+    pub ready: Option<bool>,
+    pub corrid: Option<CorrelationId>,
+}
+
+impl SequenceControls {
+    /// Controls of `sequence_batching`, as JSON from `Model::model_config`
+    pub fn from_model_config(model_config: &str) -> Result<Self, Error> {
+        let config: serde_json::Value = serde_json::from_str(model_config)?;
+        let Some(sequence_batching) = config.get("sequence_batching") else {
+            return Err("Model config has no sequence_batching".into());
+        };
+        let mut controls = Self::default();
+        let Some(control_inputs) = sequence_batching.get("control_input") else {
+            return Ok(controls);
+        };
+        let control_inputs = control_inputs.as_array().ok_or("control_input is not an array")?;
+        for control_input in control_inputs {
+            let input_name = control_input.get("name").and_then(|name| name.as_str())
+                .ok_or_else(|| format!("control_input without name {control_input}"))?;
+            let control = control_input.get("control").and_then(|control| control.as_array())
+                .ok_or_else(|| format!("control_input without control {control_input}"))?;
+            for control in control {
+                let (kind, control) = Control::from_json(input_name, control)?;
+                let slot = match kind {
+                    ControlKind::Start => &mut controls.start,
+                    ControlKind::End => &mut controls.end,
+                    ControlKind::Ready => &mut controls.ready,
+                    ControlKind::CorrelationId => &mut controls.corrid,
+                };
+                if slot.replace(control).is_some() {
+                    return Err(format!("Duplicate control {kind:?} in control_input").into());
+                }
+            }
+        }
+        Ok(controls)
+    }
+
+    /// Input name of control `kind`, if configured
+    pub fn input_name(&self, kind: ControlKind) -> Option<&str> {
+        let control = match kind {
+            ControlKind::Start => &self.start,
+            ControlKind::End => &self.end,
+            ControlKind::Ready => &self.ready,
+            ControlKind::CorrelationId => &self.corrid,
+        };
+        control.as_ref().map(|control| control.input_name.as_str())
+    }
+
+    /// Read the control tensors of `request`
+    pub fn read(&self, request: &Request) -> Result<SequenceControl, Error> {
+        let flag = |control: &Option<Control>| -> Result<Option<bool>, Error> {
+            control.as_ref().map(|control| control.read_flag(request)).transpose()
+        };
+        Ok(SequenceControl {
+            start: flag(&self.start)?,
+            end: flag(&self.end)?,
+            ready: flag(&self.ready)?,
+            corrid: self.corrid.as_ref().map(|control| control.read_corrid(request)).transpose()?,
+        })
+    }
+}
+
+impl Control {
+    fn from_json(input_name: &str, json: &serde_json::Value) -> Result<(ControlKind, Control), Error> {
+        let kind: ControlKind = json.get("kind").and_then(|kind| kind.as_str())
+            .ok_or_else(|| format!("control without kind {json}"))?
+            .parse()?;
+        // the JSON form of the config may hold all of them, empty but one
+        let pair = |name: &str| {
+            json.get(name).and_then(|values| values.as_array()).filter(|values| !values.is_empty())
+        };
+        let invalid = || format!("Invalid false/true values of control {input_name} {json}");
+        let false_true = if let Some(values) = pair("int32_false_true") {
+            let values: Vec<i32> = values.iter()
+                .map(|value| value.as_i64().and_then(|value| i32::try_from(value).ok()))
+                .collect::<Option<_>>().ok_or_else(invalid)?;
+            Some(FalseTrue::Int32(values.try_into().map_err(|_| invalid())?))
+        } else if let Some(values) = pair("fp32_false_true") {
+            let values: Vec<f32> = values.iter()
+                .map(|value| value.as_f64().map(|value| value as f32))
+                .collect::<Option<_>>().ok_or_else(invalid)?;
+            Some(FalseTrue::Fp32(values.try_into().map_err(|_| invalid())?))
+        } else if let Some(values) = pair("bool_false_true") {
+            let values: Vec<bool> = values.iter()
+                .map(|value| value.as_bool())
+                .collect::<Option<_>>().ok_or_else(invalid)?;
+            Some(FalseTrue::Bool(values.try_into().map_err(|_| invalid())?))
+        } else {
+            None
+        };
+
+        let data_type = match (kind, false_true) {
+            (ControlKind::CorrelationId, None) => {
+                let data_type = json.get("data_type").and_then(|data_type| data_type.as_str())
+                    .ok_or_else(|| format!("CORRID control {input_name} without data_type {json}"))?;
+                data_type.parse()?
+            },
+            (ControlKind::CorrelationId, Some(_)) | (_, None) => return Err(invalid().into()),
+            (_, Some(FalseTrue::Int32(_))) => DataType::INT32,
+            (_, Some(FalseTrue::Fp32(_))) => DataType::FP32,
+            (_, Some(FalseTrue::Bool(_))) => DataType::BOOL,
+        };
+        Ok((kind, Control { input_name: input_name.to_string(), false_true, data_type }))
+    }
+
+    fn input(&self, request: &Request) -> Result<Input, Error> {
+        let input = request.get_input(&self.input_name)?;
+        let datatype = input.properties()?.datatype;
+        if datatype != self.data_type {
+            return Err(format!("Control {} is {datatype}, expected {}", self.input_name, self.data_type).into());
+        }
+        Ok(input)
+    }
+
+    /// True if the (first) value is the configured true value
+    fn read_flag(&self, request: &Request) -> Result<bool, Error> {
+        let input = self.input(request)?;
+        let empty = || format!("Control {} is empty", self.input_name);
+        let flag = match self.false_true {
+            Some(FalseTrue::Int32([_, on])) => *input.to_vec::<i32>()?.first().ok_or_else(empty)? == on,
+            Some(FalseTrue::Fp32([_, on])) => *input.to_vec::<f32>()?.first().ok_or_else(empty)? == on,
+            Some(FalseTrue::Bool([_, on])) => *input.to_vec::<bool>()?.first().ok_or_else(empty)? == on,
+            None => return Err(format!("Control {} is not a flag", self.input_name).into()),
+        };
+        Ok(flag)
+    }
+
+    fn read_corrid(&self, request: &Request) -> Result<CorrelationId, Error> {
+        let input = self.input(request)?;
+        let empty = || format!("Control {} is empty", self.input_name);
+        let id = match self.data_type {
+            DataType::BYTES => CorrelationId::String(input.as_string()?),
+            DataType::UINT32 | DataType::UINT64 => {
+                CorrelationId::U64(*input.to_vec_widening::<u64>()?.first().ok_or_else(empty)?)
+            },
+            DataType::INT32 | DataType::INT64 => {
+                let id = *input.to_vec_widening::<i64>()?.first().ok_or_else(empty)?;
+                CorrelationId::U64(u64::try_from(id)
+                    .map_err(|_| format!("Control {} holds negative ID {id}", self.input_name))?)
+            },
+            data_type => return Err(format!("CORRID of {data_type} not supported").into()),
+        };
+        Ok(id)
+    }
+}
+
+#[test]
+fn test_sequence_store() {
+    use triton_sys::{
+        tritonserver_requestflag_enum_TRITONSERVER_REQUEST_FLAG_SEQUENCE_END as END,
+        tritonserver_requestflag_enum_TRITONSERVER_REQUEST_FLAG_SEQUENCE_START as START,
+    };
+    let mut store = SequenceStore::<Vec<u32>>::new(Some(Duration::from_millis(10)));
+    let now = Instant::now();
+    let later = |ms| now + Duration::from_millis(ms);
+
+    let id = CorrelationId::from;
+    store.get_at(id(7), START.into(), now, Vec::new).unwrap().push(1);
+    store.get_at(id(8), START.into(), now, Vec::new).unwrap().push(10);
+    store.get_at(id(7), 0.into(), later(5), Vec::new).unwrap().push(2);
+    assert!(store.get_at(id(9), 0.into(), later(5), Vec::new).is_err());
+    assert!(store.get_at(id(0), START.into(), later(5), Vec::new).is_err());
+    assert!(store.get_at("".into(), START.into(), later(5), Vec::new).is_err());
+    assert_eq!(2, store.len());
+
+    // 8 idle for 12ms, evicted
+    let sequence = store.get_at(id(7), END.into(), later(12), Vec::new).unwrap();
+    assert_eq!(vec![1, 2], *sequence);
+    assert!(sequence.is_end());
+    drop(sequence);
+    assert!(store.is_empty());
+    assert!(store.get_at(id(8), 0.into(), later(12), Vec::new).is_err());
+
+    // START and END in one request, correlation ID reused
+    let sequence = store.get_at(id(7), (START | END).into(), later(20), || vec![3]).unwrap();
+    assert_eq!(vec![3], *sequence);
+    drop(sequence);
+    assert!(store.is_empty());
+
+    store.get_at(id(5), START.into(), now, Vec::new).unwrap();
+    assert_eq!(vec![(id(5), vec![])], store.evict_idle_at(later(11)));
+}
+
+#[test]
+fn test_sequence_store_string_id() {
+    use crate::stub::FakeRequest;
+    let mut store = SequenceStore::<u32>::new(None);
+    let mut fake = FakeRequest::new(vec![]);
+    fake.correlation_id_string = Some(c"6f1c0e52-1b2a-4c1e-9d3f-7f8e0a9b1c2d".to_owned());
+    fake.flags = triton_sys::tritonserver_requestflag_enum_TRITONSERVER_REQUEST_FLAG_SEQUENCE_START;
+    *store.get(&fake.as_request(), || 1).unwrap() += 1;
+    fake.flags = 0;
+    let sequence = store.get(&fake.as_request(), || 0).unwrap();
+    assert_eq!((&CorrelationId::from("6f1c0e52-1b2a-4c1e-9d3f-7f8e0a9b1c2d"), 2),
+               (sequence.correlation_id(), *sequence));
+    drop(sequence);
+    assert_eq!(Some(2), store.remove(&"6f1c0e52-1b2a-4c1e-9d3f-7f8e0a9b1c2d".into()));
+}
+
+#[test]
+fn test_sequence_store_from_model_config() {
+    let store = SequenceStore::<()>::from_model_config(
+        r#"{"sequence_batching": {"max_sequence_idle_microseconds": "5000000"}}"#).unwrap();
+    assert_eq!(Some(Duration::from_secs(5)), store.idle_timeout);
+    let store = SequenceStore::<()>::from_model_config(
+        r#"{"sequence_batching": {"max_sequence_idle_microseconds": 2000}}"#).unwrap();
+    assert_eq!(Some(Duration::from_millis(2)), store.idle_timeout);
+    let store = SequenceStore::<()>::from_model_config(r#"{"sequence_batching": {}}"#).unwrap();
+    assert_eq!(Some(DEFAULT_IDLE_TIMEOUT), store.idle_timeout);
+    assert!(SequenceStore::<()>::from_model_config(r#"{"name": "m"}"#).is_err());
+}
+
+#[test]
+fn test_sequence_controls() {
+    use crate::stub::{bytes, FakeInput, FakeRequest};
+    let config = r#"{"sequence_batching": {"control_input": [
+        {"name": "START", "control": [{"kind": "CONTROL_SEQUENCE_START", "int32_false_true": [0, 1],
+                                       "fp32_false_true": [], "bool_false_true": [], "data_type": "TYPE_INVALID"}]},
+        {"name": "END", "control": [{"kind": "CONTROL_SEQUENCE_END", "fp32_false_true": [0.0, 1.0]}]},
+        {"name": "READY", "control": [{"kind": "CONTROL_SEQUENCE_READY", "bool_false_true": [false, true]}]},
+        {"name": "CORRID", "control": [{"kind": "CONTROL_SEQUENCE_CORRID", "data_type": "TYPE_STRING"}]}]}}"#;
+    let controls = SequenceControls::from_model_config(config).unwrap();
+    assert_eq!(Some("CORRID"), controls.input_name(ControlKind::CorrelationId));
+
+    let input = |name, datatype, data| FakeInput::cpu(name, datatype, &[1], data);
+    let mut fake = FakeRequest::new(vec![
+        input("START", DataType::INT32, bytes(&[1i32])),
+        input("END", DataType::FP32, bytes(&[0f32])),
+        input("READY", DataType::BOOL, vec![1]),
+        input("CORRID", DataType::BYTES, crate::encode_string("a2c4")),
+    ]);
+    let control = controls.read(&fake.as_request()).unwrap();
+    assert_eq!(SequenceControl {
+        start: Some(true),
+        end: Some(false),
+        ready: Some(true),
+        corrid: Some(CorrelationId::from("a2c4")),
+    }, control);
+
+    let config = r#"{"sequence_batching": {"control_input": [
+        {"name": "CORRID", "control": [{"kind": "CONTROL_SEQUENCE_CORRID", "data_type": "TYPE_UINT64"}]}]}}"#;
+    let controls = SequenceControls::from_model_config(config).unwrap();
+    let mut fake = FakeRequest::new(vec![input("CORRID", DataType::UINT64, bytes(&[42u64]))]);
+    let control = controls.read(&fake.as_request()).unwrap();
+    assert_eq!((None, Some(CorrelationId::U64(42))), (control.start, control.corrid));
+    let mut fake = FakeRequest::new(vec![input("CORRID", DataType::INT32, bytes(&[42i32]))]);
+    assert!(controls.read(&fake.as_request()).is_err());
+
+    assert_eq!(SequenceControls::default(),
+               SequenceControls::from_model_config(r#"{"sequence_batching": {}}"#).unwrap());
+    let invalid = r#"{"sequence_batching": {"control_input": [
+        {"name": "START", "control": [{"kind": "CONTROL_SEQUENCE_START", "int32_false_true": [1]}]}]}}"#;
+    assert!(SequenceControls::from_model_config(invalid).is_err());
+}
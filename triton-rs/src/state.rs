@@ -0,0 +1,190 @@
+//! Implicit state management for sequence models, see the `state` section of
+//! `sequence_batching` in the model config. The input state arrives as a
+//! regular input, the output state is written to a State and committed with
+//! `update`.
+
+use crate::{check_err, DataType, Error, MemoryType, Request};
+use crate::data_type::{data_type_of, SupportedTypes};
+use libc::c_void;
+#[cfg(feature = "ndarray")]
+use ndarray::{ArrayViewMut, IxDyn};
+use std::ffi::CString;
+use std::marker::PhantomData;
+use std::ptr;
+
+/// Output state of a sequence request, its buffer allocated on creation
+pub struct State<'a> {
+    ptr: *mut triton_sys::TRITONBACKEND_State,
+    data_type: DataType,
+    shape: Vec<i64>,
+    buffer: *mut c_void,
+    byte_size: usize,
+    memory_type: MemoryType,
+    _request: PhantomData<&'a Request>,
+}
+
+/// Attributes of a State buffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BufferAttributes {
+    pub memory_type: MemoryType,
+    pub memory_type_id: i64,
+    pub byte_size: usize,
+}
+
+impl Request {
+    /// Input state `input_name`, as configured in the model config. Same as
+    /// `get_input`, states are passed as inputs.
+    pub fn state_input(&self, input_name: &str) -> Result<crate::request::Input, Error> {
+        self.get_input(input_name)
+    }
+
+    /// Create output state `output_name` and allocate its buffer, in CPU memory
+    /// if possible. The state of the sequence only changes on `State::update`.
+    pub fn new_state(&self, output_name: &str, data_type: DataType, shape: &[i64]) -> Result<State<'_>, Error> {
+        if data_type.byte_size() == 0 {
+            return Err(format!("State {output_name} of {data_type} not supported").into());
+        }
+        let byte_size = crate::element_count(shape)? * data_type.byte_size() as usize;
+        let name = CString::new(output_name)?;
+        let mut state: *mut triton_sys::TRITONBACKEND_State = ptr::null_mut();
+        check_err(unsafe {
+            triton_sys::TRITONBACKEND_StateNew(
+                &mut state,
+                self.as_ptr(),
+                name.as_ptr(),
+                data_type as u32,
+                shape.as_ptr(),
+                shape.len().try_into()?,
+            )
+        })?;
+
+        let mut buffer: *mut c_void = ptr::null_mut();
+        let mut memory_type = MemoryType::CPU as u32;
+        let mut memory_type_id = 0;
+        check_err(unsafe {
+            triton_sys::TRITONBACKEND_StateBuffer(
+                state,
+                &mut buffer,
+                byte_size as u64,
+                &mut memory_type,
+                &mut memory_type_id,
+            )
+        })?;
+        if buffer.is_null() && byte_size > 0 {
+            return Err(format!("Failed to allocate state {output_name} buffer").into());
+        }
+        let memory_type = MemoryType::try_from(memory_type)?;
+        if memory_type.is_cpu() && byte_size > 0 {
+            // all zero bits are valid for every SupportedTypes
+            unsafe { ptr::write_bytes(buffer as *mut u8, 0, byte_size) };
+        }
+
+        Ok(State {
+            ptr: state,
+            data_type,
+            shape: shape.to_vec(),
+            buffer,
+            byte_size,
+            memory_type,
+            _request: PhantomData,
+        })
+    }
+}
+
+impl State<'_> {
+    pub fn data_type(&self) -> DataType {
+        self.data_type
+    }
+
+    pub fn shape(&self) -> &[i64] {
+        &self.shape
+    }
+
+    pub fn memory_type(&self) -> MemoryType {
+        self.memory_type
+    }
+
+    /// State buffer as typed slice, zero filled, refusing memory the host
+    /// cannot dereference
+    pub fn as_mut_slice<T>(&mut self) -> Result<&mut [T], Error> where T: Copy + SupportedTypes {
+        let data_type = data_type_of::<T>()?;
+        if data_type != self.data_type {
+            return Err(format!("DataType does not match {data_type}, state is {}", self.data_type).into());
+        }
+        self.memory_type.check_cpu("State buffer")?;
+        if self.byte_size == 0 {
+            return Ok(&mut []);
+        }
+        if !(self.buffer as usize).is_multiple_of(std::mem::align_of::<T>()) {
+            return Err(format!("State buffer is not aligned for {data_type}").into());
+        }
+        let len = self.byte_size / std::mem::size_of::<T>();
+        Ok(unsafe { std::slice::from_raw_parts_mut(self.buffer as *mut T, len) })
+    }
+
+    /// Like `as_mut_slice`, as ndarray
+    #[cfg(feature = "ndarray")]
+    pub fn as_array_mut<T>(&mut self) -> Result<ArrayViewMut<'_, T, IxDyn>, Error> where T: Copy + SupportedTypes {
+        let shape = crate::array_shape::<IxDyn>(&self.shape)?;
+        Ok(ArrayViewMut::from_shape(shape, self.as_mut_slice()?)?)
+    }
+
+    pub fn buffer_attributes(&self) -> Result<BufferAttributes, Error> {
+        let mut attributes: *mut triton_sys::TRITONSERVER_BufferAttributes = ptr::null_mut();
+        check_err(unsafe {
+            triton_sys::TRITONBACKEND_StateBufferAttributes(self.ptr, &mut attributes)
+        })?;
+        // attributes are owned by the state, don't delete
+        let mut memory_type = 0;
+        let mut memory_type_id = 0;
+        let mut byte_size = 0;
+        check_err(unsafe {
+            triton_sys::TRITONSERVER_BufferAttributesMemoryType(attributes, &mut memory_type)
+        })?;
+        check_err(unsafe {
+            triton_sys::TRITONSERVER_BufferAttributesMemoryTypeId(attributes, &mut memory_type_id)
+        })?;
+        check_err(unsafe {
+            triton_sys::TRITONSERVER_BufferAttributesByteSize(attributes, &mut byte_size)
+        })?;
+        Ok(BufferAttributes { memory_type: memory_type.try_into()?, memory_type_id, byte_size })
+    }
+
+    /// Make this the state of the sequence, for its next request
+    pub fn update(self) -> Result<(), Error> {
+        check_err(unsafe { triton_sys::TRITONBACKEND_StateUpdate(self.ptr) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::stub::{bytes, FakeRequest};
+    use crate::{DataType, MemoryType};
+
+    #[test]
+    fn test_state() {
+        let mut fake = FakeRequest::new(vec![]);
+        let request = fake.as_request();
+        let mut state = request.new_state("OUTPUT_STATE", DataType::INT32, &[2, 2]).unwrap();
+        assert_eq!(MemoryType::CPU, state.memory_type());
+        assert!(state.as_mut_slice::<f32>().is_err());
+        state.as_mut_slice::<i32>().unwrap().copy_from_slice(&[1, 2, 3, 4]);
+        let attributes = state.buffer_attributes().unwrap();
+        assert_eq!((MemoryType::CPU, 16), (attributes.memory_type, attributes.byte_size));
+        assert!(request.new_state("BYTES_STATE", DataType::BYTES, &[1]).is_err());
+        #[cfg(feature = "ndarray")]
+        {
+            let mut state = request.new_state("ARRAY_STATE", DataType::FP64, &[3]).unwrap();
+            state.as_array_mut::<f64>().unwrap().fill(0.5);
+            drop(state); // not updated
+        }
+        state.update().unwrap();
+        drop(request);
+
+        assert_eq!("OUTPUT_STATE", fake.states[0].name.to_str().unwrap());
+        assert_eq!((DataType::INT32, vec![2, 2]), (fake.states[0].datatype, fake.states[0].shape.clone()));
+        assert_eq!(bytes(&[1i32, 2, 3, 4]), fake.states[0].data);
+        assert!(fake.states[0].updated);
+        assert!(fake.states.iter().skip(1).all(|state| !state.updated));
+    }
+}
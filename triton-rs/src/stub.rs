@@ -0,0 +1,902 @@
+//! TEST STUBS: minimal stand-ins for the Triton C API, so that unit tests
+//! link and run without libtritonserver. Opaque Triton handles are backed by
+//! the Fake* structs below.
+
+use crate::{DataType, MemoryType, ParameterValue, ResponseFactory, ResponseSender, SupportedTypes};
+use crate::request::{Input, Request};
+use std::ffi::{c_char, c_void, CStr, CString};
+
+/// Native endian bytes of `data`, as tensors are laid out in Triton buffers
+pub(crate) fn bytes<T: Copy + SupportedTypes>(data: &[T]) -> Vec<u8> {
+    // SAFETY: tensor element types are numbers (or bool) without padding
+    unsafe { std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data)) }.to_vec()
+}
+
+/// Data and memory type of each buffer of an input
+pub(crate) type FakeBuffers = Vec<(Vec<u8>, MemoryType)>;
+
+pub(crate) struct FakeInput {
+    pub name: CString,
+    pub datatype: DataType,
+    pub shape: Vec<i64>,
+    pub buffers: Vec<(Vec<u8>, MemoryType)>,
+    /// Buffers placed for a host policy, instead of the fallback `buffers`
+    pub host_policy_buffers: Vec<(CString, FakeBuffers)>,
+}
+
+impl FakeInput {
+    pub fn new(datatype: DataType, shape: &[i64], buffers: Vec<(Vec<u8>, MemoryType)>) -> Self {
+        let name = CString::new("fake").unwrap();
+        Self { name, datatype, shape: shape.to_vec(), buffers, host_policy_buffers: Vec::new() }
+    }
+
+    /// Input `name` with `data` in a single CPU buffer
+    pub fn cpu(name: &str, datatype: DataType, shape: &[i64], data: Vec<u8>) -> Self {
+        let mut input = Self::new(datatype, shape, vec![(data, MemoryType::CPU)]);
+        input.name = CString::new(name).unwrap();
+        input
+    }
+
+    /// Buffers for `host_policy`, or the fallback buffers as in Triton
+    fn buffers_for(&self, host_policy: *const c_char) -> &[(Vec<u8>, MemoryType)] {
+        if host_policy.is_null() {
+            return &self.buffers;
+        }
+        let host_policy = unsafe { CStr::from_ptr(host_policy) };
+        self.host_policy_buffers.iter()
+            .find(|(name, _)| name.as_c_str() == host_policy)
+            .map_or(&self.buffers, |(_, buffers)| buffers)
+    }
+
+    pub fn as_input(&mut self) -> Input {
+        Input::from_ptr(self as *mut FakeInput as *mut triton_sys::TRITONBACKEND_Input)
+    }
+}
+
+pub(crate) struct FakeRequest {
+    pub inputs: Vec<FakeInput>,
+    pub correlation_id: u64,
+    /// Takes precedence over `correlation_id`, as Triton holds one or the other
+    pub correlation_id_string: Option<CString>,
+    pub flags: u32,
+    pub cancelled: bool,
+    #[allow(clippy::vec_box)] // handed out TRITONBACKEND_State pointers must stay put
+    pub states: Vec<Box<FakeState>>,
+    #[allow(clippy::vec_box)] // handed out TRITONBACKEND_Response pointers must stay put
+    pub responses: Vec<Box<FakeResponse>>,
+    /// Returned by every TRITONBACKEND_ResponseFactoryNew
+    pub factory: FakeResponseFactory,
+}
+
+impl FakeRequest {
+    pub fn new(inputs: Vec<FakeInput>) -> Self {
+        Self {
+            inputs,
+            correlation_id: 0,
+            correlation_id_string: None,
+            flags: 0,
+            cancelled: false,
+            states: vec![],
+            responses: vec![],
+            factory: FakeResponseFactory::default(),
+        }
+    }
+
+    /// Request with the single input "fake", holding `data` in a CPU buffer
+    pub fn with_input<T: Copy + SupportedTypes>(datatype: DataType, shape: &[i64], data: &[T]) -> Self {
+        Self::new(vec![FakeInput::cpu("fake", datatype, shape, bytes(data))])
+    }
+
+    pub fn as_request(&mut self) -> Request {
+        Request::from_ptr(self as *mut FakeRequest as *mut triton_sys::TRITONBACKEND_Request)
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct FakeResponse {
+    #[allow(clippy::vec_box)] // handed out TRITONBACKEND_Output pointers must stay put
+    pub outputs: Vec<Box<FakeOutput>>,
+    /// Flags and error message passed to TRITONBACKEND_ResponseSend
+    pub sent: Option<(u32, Option<String>)>,
+    pub parameters: Vec<(String, ParameterValue)>,
+}
+
+impl FakeResponse {
+    pub fn as_ptr(&mut self) -> *mut triton_sys::TRITONBACKEND_Response {
+        self as *mut FakeResponse as *mut triton_sys::TRITONBACKEND_Response
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct FakeResponseFactory {
+    #[allow(clippy::vec_box)] // handed out TRITONBACKEND_Response pointers must stay put
+    pub responses: Vec<Box<FakeResponse>>,
+    /// Passed to TRITONBACKEND_ResponseFactorySendFlags
+    pub flags: Vec<u32>,
+    pub deleted: bool,
+    pub cancelled: bool,
+}
+
+impl FakeResponseFactory {
+    pub fn as_ptr(&mut self) -> *mut triton_sys::TRITONBACKEND_ResponseFactory {
+        self as *mut FakeResponseFactory as *mut triton_sys::TRITONBACKEND_ResponseFactory
+    }
+
+    pub fn as_sender(&mut self) -> ResponseSender {
+        ResponseSender::new(ResponseFactory::from_ptr(self.as_ptr()))
+    }
+}
+
+pub(crate) struct FakeOutput {
+    pub name: CString,
+    pub datatype: DataType,
+    pub shape: Vec<i64>,
+    pub data: Vec<u8>,
+}
+
+/// Parameter value as Triton hands it out, strings NUL terminated and
+/// numbers aligned
+enum FakeParameterValue {
+    String(CString),
+    Int(i64),
+    Bool(bool),
+}
+
+impl FakeParameterValue {
+    fn parameter_type(&self) -> triton_sys::TRITONSERVER_ParameterType {
+        match self {
+            Self::String(_) => triton_sys::TRITONSERVER_parametertype_enum_TRITONSERVER_PARAMETER_STRING,
+            Self::Int(_) => triton_sys::TRITONSERVER_parametertype_enum_TRITONSERVER_PARAMETER_INT,
+            Self::Bool(_) => triton_sys::TRITONSERVER_parametertype_enum_TRITONSERVER_PARAMETER_BOOL,
+        }
+    }
+
+    fn as_ptr(&self) -> *const c_void {
+        match self {
+            Self::String(value) => value.as_ptr() as *const c_void,
+            Self::Int(value) => value as *const i64 as *const c_void,
+            Self::Bool(value) => value as *const bool as *const c_void,
+        }
+    }
+}
+
+/// TRITONSERVER_InferenceResponse without outputs
+pub(crate) struct FakeInferenceResponse {
+    parameters: Vec<(CString, FakeParameterValue)>,
+}
+
+impl FakeInferenceResponse {
+    pub fn new(parameters: Vec<(&str, ParameterValue)>) -> Self {
+        let parameters = parameters.into_iter().map(|(name, value)| {
+            let value = match value {
+                ParameterValue::String(value) => FakeParameterValue::String(CString::new(value).unwrap()),
+                ParameterValue::Int(value) => FakeParameterValue::Int(value),
+                ParameterValue::Bool(value) => FakeParameterValue::Bool(value),
+            };
+            (CString::new(name).unwrap(), value)
+        });
+        Self { parameters: parameters.collect() }
+    }
+
+    pub fn as_ptr(&mut self) -> *mut triton_sys::TRITONSERVER_InferenceResponse {
+        self as *mut FakeInferenceResponse as *mut triton_sys::TRITONSERVER_InferenceResponse
+    }
+}
+
+/// Also stands in for its TRITONSERVER_BufferAttributes
+pub(crate) struct FakeState {
+    pub name: CString,
+    pub datatype: DataType,
+    pub shape: Vec<i64>,
+    pub data: Vec<u8>,
+    pub updated: bool,
+}
+
+pub(crate) struct FakeMetricFamily {
+    pub kind: triton_sys::TRITONSERVER_MetricKind,
+    pub name: String,
+    pub description: String,
+}
+
+pub(crate) struct FakeMetric {
+    pub kind: triton_sys::TRITONSERVER_MetricKind,
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+}
+
+/// String TRITONSERVER_Parameter, the only type used for metric labels
+struct FakeParameter {
+    name: String,
+    value: String,
+}
+
+/// Holds the state a model sets
+pub(crate) struct FakeModel {
+    pub state: *mut c_void,
+}
+
+impl Default for FakeModel {
+    fn default() -> Self {
+        Self { state: std::ptr::null_mut() }
+    }
+}
+
+impl FakeModel {
+    pub fn as_ptr(&mut self) -> *mut triton_sys::TRITONBACKEND_Model {
+        self as *mut FakeModel as *mut triton_sys::TRITONBACKEND_Model
+    }
+}
+
+/// Holds the state a model instance sets
+pub(crate) struct FakeModelInstance {
+    pub state: *mut c_void,
+}
+
+impl Default for FakeModelInstance {
+    fn default() -> Self {
+        Self { state: std::ptr::null_mut() }
+    }
+}
+
+impl FakeModelInstance {
+    pub fn as_ptr(&mut self) -> *mut triton_sys::TRITONBACKEND_ModelInstance {
+        self as *mut FakeModelInstance as *mut triton_sys::TRITONBACKEND_ModelInstance
+    }
+}
+
+struct FakeError {
+    code: triton_sys::TRITONSERVER_Error_Code,
+    message: CString,
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_ErrorNew(
+    code: triton_sys::TRITONSERVER_Error_Code,
+    msg: *const c_char,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let message = unsafe { CStr::from_ptr(msg) }.to_owned();
+    Box::into_raw(Box::new(FakeError { code, message })) as *mut _
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_ErrorDelete(error: *mut triton_sys::TRITONSERVER_Error) {
+    drop(unsafe { Box::from_raw(error as *mut FakeError) });
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_ErrorCode(
+    error: *mut triton_sys::TRITONSERVER_Error,
+) -> triton_sys::TRITONSERVER_Error_Code {
+    unsafe { &*(error as *mut FakeError) }.code
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_ErrorCodeString(
+    error: *mut triton_sys::TRITONSERVER_Error,
+) -> *const c_char {
+    // static, as in Triton, so it outlives the error
+    match unsafe { &*(error as *mut FakeError) }.code {
+        triton_sys::TRITONSERVER_errorcode_enum_TRITONSERVER_ERROR_INTERNAL => c"Internal".as_ptr(),
+        triton_sys::TRITONSERVER_errorcode_enum_TRITONSERVER_ERROR_NOT_FOUND => c"Not found".as_ptr(),
+        triton_sys::TRITONSERVER_errorcode_enum_TRITONSERVER_ERROR_INVALID_ARG => c"Invalid argument".as_ptr(),
+        triton_sys::TRITONSERVER_errorcode_enum_TRITONSERVER_ERROR_UNAVAILABLE => c"Unavailable".as_ptr(),
+        triton_sys::TRITONSERVER_errorcode_enum_TRITONSERVER_ERROR_UNSUPPORTED => c"Unsupported".as_ptr(),
+        triton_sys::TRITONSERVER_errorcode_enum_TRITONSERVER_ERROR_ALREADY_EXISTS => c"Already exists".as_ptr(),
+        _ => c"Unknown".as_ptr(),
+    }
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_ErrorMessage(
+    error: *mut triton_sys::TRITONSERVER_Error,
+) -> *const c_char {
+    unsafe { &*(error as *mut FakeError) }.message.as_ptr()
+}
+
+fn not_found(what: &str) -> *mut triton_sys::TRITONSERVER_Error {
+    let msg = CString::new(what).unwrap();
+    TRITONSERVER_ErrorNew(
+        triton_sys::TRITONSERVER_errorcode_enum_TRITONSERVER_ERROR_NOT_FOUND,
+        msg.as_ptr(),
+    )
+}
+
+fn invalid_arg(what: &str) -> *mut triton_sys::TRITONSERVER_Error {
+    let msg = CString::new(what).unwrap();
+    TRITONSERVER_ErrorNew(
+        triton_sys::TRITONSERVER_errorcode_enum_TRITONSERVER_ERROR_INVALID_ARG,
+        msg.as_ptr(),
+    )
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_MemoryTypeString(
+    memtype: triton_sys::TRITONSERVER_MemoryType,
+) -> *const c_char {
+    match memtype {
+        triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_CPU => c"CPU".as_ptr(),
+        triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_CPU_PINNED => c"CPU_PINNED".as_ptr(),
+        triton_sys::TRITONSERVER_memorytype_enum_TRITONSERVER_MEMORY_GPU => c"GPU".as_ptr(),
+        _ => c"<invalid>".as_ptr(),
+    }
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_RequestCorrelationId(
+    request: *mut triton_sys::TRITONBACKEND_Request,
+    id: *mut u64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let request = unsafe { &*(request as *mut FakeRequest) };
+    if request.correlation_id_string.is_some() {
+        return invalid_arg("correlation ID is a string");
+    }
+    unsafe { *id = request.correlation_id };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_RequestCorrelationIdString(
+    request: *mut triton_sys::TRITONBACKEND_Request,
+    id: *mut *const c_char,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let request = unsafe { &*(request as *mut FakeRequest) };
+    let Some(correlation_id) = &request.correlation_id_string else {
+        return invalid_arg("correlation ID is not a string");
+    };
+    unsafe { *id = correlation_id.as_ptr() };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_RequestFlags(
+    request: *mut triton_sys::TRITONBACKEND_Request,
+    flags: *mut u32,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { *flags = (*(request as *mut FakeRequest)).flags };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_RequestIsCancelled(
+    request: *mut triton_sys::TRITONBACKEND_Request,
+    is_cancelled: *mut bool,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { *is_cancelled = (*(request as *mut FakeRequest)).cancelled };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_RequestInput(
+    request: *mut triton_sys::TRITONBACKEND_Request,
+    name: *const c_char,
+    input: *mut *mut triton_sys::TRITONBACKEND_Input,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let request = unsafe { &mut *(request as *mut FakeRequest) };
+    let name = unsafe { CStr::from_ptr(name) };
+    let Some(fake) = request.inputs.iter_mut().find(|input| input.name.as_c_str() == name) else {
+        return not_found("input not found");
+    };
+    unsafe { *input = fake as *mut FakeInput as *mut _ };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_InputProperties(
+    input: *mut triton_sys::TRITONBACKEND_Input,
+    name: *mut *const c_char,
+    datatype: *mut triton_sys::TRITONSERVER_DataType,
+    shape: *mut *const i64,
+    dims_count: *mut u32,
+    byte_size: *mut u64,
+    buffer_count: *mut u32,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    TRITONBACKEND_InputPropertiesForHostPolicy(
+        input, std::ptr::null(), name, datatype, shape, dims_count, byte_size, buffer_count,
+    )
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_InputPropertiesForHostPolicy(
+    input: *mut triton_sys::TRITONBACKEND_Input,
+    host_policy_name: *const c_char,
+    name: *mut *const c_char,
+    datatype: *mut triton_sys::TRITONSERVER_DataType,
+    shape: *mut *const i64,
+    dims_count: *mut u32,
+    byte_size: *mut u64,
+    buffer_count: *mut u32,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let input = unsafe { &*(input as *mut FakeInput) };
+    let buffers = input.buffers_for(host_policy_name);
+    unsafe {
+        *name = input.name.as_ptr();
+        *datatype = input.datatype as u32;
+        *shape = input.shape.as_ptr();
+        *dims_count = input.shape.len() as u32;
+        *byte_size = buffers.iter().map(|(b, _)| b.len() as u64).sum();
+        *buffer_count = buffers.len() as u32;
+    }
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_InputBuffer(
+    input: *mut triton_sys::TRITONBACKEND_Input,
+    index: u32,
+    buffer: *mut *const c_void,
+    buffer_byte_size: *mut u64,
+    memory_type: *mut triton_sys::TRITONSERVER_MemoryType,
+    memory_type_id: *mut i64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    TRITONBACKEND_InputBufferForHostPolicy(
+        input, std::ptr::null(), index, buffer, buffer_byte_size, memory_type, memory_type_id,
+    )
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_InputBufferForHostPolicy(
+    input: *mut triton_sys::TRITONBACKEND_Input,
+    host_policy_name: *const c_char,
+    index: u32,
+    buffer: *mut *const c_void,
+    buffer_byte_size: *mut u64,
+    memory_type: *mut triton_sys::TRITONSERVER_MemoryType,
+    memory_type_id: *mut i64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let input = unsafe { &*(input as *mut FakeInput) };
+    let Some((data, data_memory_type)) = input.buffers_for(host_policy_name).get(index as usize) else {
+        return not_found("buffer index out of range");
+    };
+    unsafe {
+        *buffer = data.as_ptr() as *const c_void;
+        *buffer_byte_size = data.len() as u64;
+        *memory_type = *data_memory_type as u32;
+        *memory_type_id = 0;
+    }
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseOutput(
+    response: *mut triton_sys::TRITONBACKEND_Response,
+    output: *mut *mut triton_sys::TRITONBACKEND_Output,
+    name: *const c_char,
+    datatype: triton_sys::TRITONSERVER_DataType,
+    shape: *const i64,
+    dims_count: u32,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let response = unsafe { &mut *(response as *mut FakeResponse) };
+    let mut fake = Box::new(FakeOutput {
+        name: unsafe { CStr::from_ptr(name) }.to_owned(),
+        datatype: datatype.into(),
+        shape: unsafe { std::slice::from_raw_parts(shape, dims_count as usize) }.to_vec(),
+        data: vec![],
+    });
+    unsafe { *output = fake.as_mut() as *mut FakeOutput as *mut _ };
+    response.outputs.push(fake);
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_OutputBuffer(
+    output: *mut triton_sys::TRITONBACKEND_Output,
+    buffer: *mut *mut c_void,
+    buffer_byte_size: u64,
+    memory_type: *mut triton_sys::TRITONSERVER_MemoryType,
+    memory_type_id: *mut i64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let output = unsafe { &mut *(output as *mut FakeOutput) };
+    // not zeroed, like Triton's buffers
+    output.data = vec![0xAA; buffer_byte_size as usize];
+    unsafe {
+        *buffer = output.data.as_mut_ptr() as *mut c_void;
+        *memory_type = MemoryType::CPU as u32;
+        *memory_type_id = 0;
+    }
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseDelete(
+    _response: *mut triton_sys::TRITONBACKEND_Response,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    std::ptr::null_mut() // FakeResponse is owned by the test
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseNew(
+    response: *mut *mut triton_sys::TRITONBACKEND_Response,
+    request: *mut triton_sys::TRITONBACKEND_Request,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let request = unsafe { &mut *(request as *mut FakeRequest) };
+    let mut fake = Box::<FakeResponse>::default();
+    unsafe { *response = fake.as_ptr() };
+    request.responses.push(fake);
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseFactoryNew(
+    factory: *mut *mut triton_sys::TRITONBACKEND_ResponseFactory,
+    request: *mut triton_sys::TRITONBACKEND_Request,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let request = unsafe { &mut *(request as *mut FakeRequest) };
+    unsafe { *factory = request.factory.as_ptr() };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseNewFromFactory(
+    response: *mut *mut triton_sys::TRITONBACKEND_Response,
+    factory: *mut triton_sys::TRITONBACKEND_ResponseFactory,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let factory = unsafe { &mut *(factory as *mut FakeResponseFactory) };
+    let mut fake = Box::<FakeResponse>::default();
+    unsafe { *response = fake.as_ptr() };
+    factory.responses.push(fake);
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseFactorySendFlags(
+    factory: *mut triton_sys::TRITONBACKEND_ResponseFactory,
+    send_flags: u32,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { &mut *(factory as *mut FakeResponseFactory) }.flags.push(send_flags);
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseFactoryIsCancelled(
+    factory: *mut triton_sys::TRITONBACKEND_ResponseFactory,
+    is_cancelled: *mut bool,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { *is_cancelled = (*(factory as *mut FakeResponseFactory)).cancelled };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseFactoryDelete(
+    factory: *mut triton_sys::TRITONBACKEND_ResponseFactory,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { &mut *(factory as *mut FakeResponseFactory) }.deleted = true;
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseSend(
+    response: *mut triton_sys::TRITONBACKEND_Response,
+    send_flags: u32,
+    error: *mut triton_sys::TRITONSERVER_Error,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let response = unsafe { &mut *(response as *mut FakeResponse) };
+    let message = (!error.is_null()).then(|| {
+        let message = unsafe { CStr::from_ptr(TRITONSERVER_ErrorMessage(error)) };
+        let message = message.to_string_lossy().into_owned();
+        TRITONSERVER_ErrorDelete(error);
+        message
+    });
+    response.sent = Some((send_flags, message));
+    std::ptr::null_mut()
+}
+
+fn set_parameter(
+    response: *mut triton_sys::TRITONBACKEND_Response,
+    name: *const c_char,
+    value: ParameterValue,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let response = unsafe { &mut *(response as *mut FakeResponse) };
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+    response.parameters.push((name, value));
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseSetStringParameter(
+    response: *mut triton_sys::TRITONBACKEND_Response,
+    name: *const c_char,
+    value: *const c_char,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let value = unsafe { CStr::from_ptr(value) }.to_string_lossy().into_owned();
+    set_parameter(response, name, ParameterValue::String(value))
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseSetIntParameter(
+    response: *mut triton_sys::TRITONBACKEND_Response,
+    name: *const c_char,
+    value: i64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    set_parameter(response, name, ParameterValue::Int(value))
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ResponseSetBoolParameter(
+    response: *mut triton_sys::TRITONBACKEND_Response,
+    name: *const c_char,
+    value: bool,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    set_parameter(response, name, ParameterValue::Bool(value))
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_InferenceResponseOutputCount(
+    _inference_response: *mut triton_sys::TRITONSERVER_InferenceResponse,
+    count: *mut u32,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { *count = 0 };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+extern "C" fn TRITONSERVER_InferenceResponseOutput(
+    _inference_response: *mut triton_sys::TRITONSERVER_InferenceResponse,
+    _index: u32,
+    _name: *mut *const c_char,
+    _datatype: *mut triton_sys::TRITONSERVER_DataType,
+    _shape: *mut *const i64,
+    _dim_count: *mut u64,
+    _base: *mut *const c_void,
+    _byte_size: *mut usize,
+    _memory_type: *mut triton_sys::TRITONSERVER_MemoryType,
+    _memory_type_id: *mut i64,
+    _userp: *mut *mut c_void,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    invalid_arg("output index out of range")
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_InferenceResponseParameterCount(
+    inference_response: *mut triton_sys::TRITONSERVER_InferenceResponse,
+    count: *mut u32,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let response = unsafe { &*(inference_response as *mut FakeInferenceResponse) };
+    unsafe { *count = response.parameters.len() as u32 };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_InferenceResponseParameter(
+    inference_response: *mut triton_sys::TRITONSERVER_InferenceResponse,
+    index: u32,
+    name: *mut *const c_char,
+    parameter_type: *mut triton_sys::TRITONSERVER_ParameterType,
+    vvalue: *mut *const c_void,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let response = unsafe { &*(inference_response as *mut FakeInferenceResponse) };
+    let Some((parameter_name, value)) = response.parameters.get(index as usize) else {
+        return invalid_arg("parameter index out of range");
+    };
+    unsafe {
+        *name = parameter_name.as_ptr();
+        *parameter_type = value.parameter_type();
+        *vvalue = value.as_ptr();
+    }
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_StateNew(
+    state: *mut *mut triton_sys::TRITONBACKEND_State,
+    request: *mut triton_sys::TRITONBACKEND_Request,
+    name: *const c_char,
+    datatype: triton_sys::TRITONSERVER_DataType,
+    shape: *const i64,
+    dims_count: u32,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let request = unsafe { &mut *(request as *mut FakeRequest) };
+    let mut fake = Box::new(FakeState {
+        name: unsafe { CStr::from_ptr(name) }.to_owned(),
+        datatype: datatype.into(),
+        shape: unsafe { std::slice::from_raw_parts(shape, dims_count as usize) }.to_vec(),
+        data: vec![],
+        updated: false,
+    });
+    unsafe { *state = fake.as_mut() as *mut FakeState as *mut _ };
+    request.states.push(fake);
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_StateBuffer(
+    state: *mut triton_sys::TRITONBACKEND_State,
+    buffer: *mut *mut c_void,
+    buffer_byte_size: u64,
+    memory_type: *mut triton_sys::TRITONSERVER_MemoryType,
+    memory_type_id: *mut i64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let state = unsafe { &mut *(state as *mut FakeState) };
+    state.data = vec![0xAA; buffer_byte_size as usize]; // not zeroed, like Triton
+    unsafe {
+        *buffer = state.data.as_mut_ptr() as *mut c_void;
+        *memory_type = MemoryType::CPU as u32;
+        *memory_type_id = 0;
+    }
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_StateUpdate(
+    state: *mut triton_sys::TRITONBACKEND_State,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { &mut *(state as *mut FakeState) }.updated = true;
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_StateBufferAttributes(
+    state: *mut triton_sys::TRITONBACKEND_State,
+    buffer_attributes: *mut *mut triton_sys::TRITONSERVER_BufferAttributes,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { *buffer_attributes = state as *mut _ };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_BufferAttributesMemoryType(
+    _buffer_attributes: *mut triton_sys::TRITONSERVER_BufferAttributes,
+    memory_type: *mut triton_sys::TRITONSERVER_MemoryType,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { *memory_type = MemoryType::CPU as u32 };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_BufferAttributesMemoryTypeId(
+    _buffer_attributes: *mut triton_sys::TRITONSERVER_BufferAttributes,
+    memory_type_id: *mut i64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { *memory_type_id = 0 };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_BufferAttributesByteSize(
+    buffer_attributes: *mut triton_sys::TRITONSERVER_BufferAttributes,
+    byte_size: *mut usize,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let state = unsafe { &*(buffer_attributes as *mut FakeState) };
+    unsafe { *byte_size = state.data.len() };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_ParameterNew(
+    name: *const c_char,
+    _type: triton_sys::TRITONSERVER_ParameterType,
+    value: *const c_void,
+) -> *mut triton_sys::TRITONSERVER_Parameter {
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+    let value = unsafe { CStr::from_ptr(value as *const c_char) }.to_string_lossy().into_owned();
+    // Fails like Triton does on errors, here for empty values
+    if value.is_empty() {
+        return std::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(FakeParameter { name, value })) as *mut _
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_ParameterDelete(parameter: *mut triton_sys::TRITONSERVER_Parameter) {
+    drop(unsafe { Box::from_raw(parameter as *mut FakeParameter) });
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_MetricFamilyNew(
+    family: *mut *mut triton_sys::TRITONSERVER_MetricFamily,
+    kind: triton_sys::TRITONSERVER_MetricKind,
+    name: *const c_char,
+    description: *const c_char,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let name = unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned();
+    let description = unsafe { CStr::from_ptr(description) }.to_string_lossy().into_owned();
+    let fake = Box::new(FakeMetricFamily { kind, name, description });
+    unsafe { *family = Box::into_raw(fake) as *mut _ };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_MetricFamilyDelete(
+    family: *mut triton_sys::TRITONSERVER_MetricFamily,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    drop(unsafe { Box::from_raw(family as *mut FakeMetricFamily) });
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_MetricNew(
+    metric: *mut *mut triton_sys::TRITONSERVER_Metric,
+    family: *mut triton_sys::TRITONSERVER_MetricFamily,
+    labels: *mut *const triton_sys::TRITONSERVER_Parameter,
+    label_count: u64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let kind = unsafe { &*(family as *mut FakeMetricFamily) }.kind;
+    let labels = (0..label_count as usize)
+        .map(|index| {
+            let label = unsafe { &*(*labels.add(index) as *const FakeParameter) };
+            (label.name.clone(), label.value.clone())
+        })
+        .collect();
+    let fake = Box::new(FakeMetric { kind, labels, value: 0.0 });
+    unsafe { *metric = Box::into_raw(fake) as *mut _ };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_MetricDelete(
+    metric: *mut triton_sys::TRITONSERVER_Metric,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    drop(unsafe { Box::from_raw(metric as *mut FakeMetric) });
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_MetricValue(
+    metric: *mut triton_sys::TRITONSERVER_Metric,
+    value: *mut f64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { *value = (*(metric as *mut FakeMetric)).value };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_MetricIncrement(
+    metric: *mut triton_sys::TRITONSERVER_Metric,
+    value: f64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let metric = unsafe { &mut *(metric as *mut FakeMetric) };
+    if metric.kind == triton_sys::TRITONSERVER_metrickind_enum_TRITONSERVER_METRIC_KIND_COUNTER && value < 0.0 {
+        return invalid_arg("Counters cannot be decremented");
+    }
+    metric.value += value;
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONSERVER_MetricSet(
+    metric: *mut triton_sys::TRITONSERVER_Metric,
+    value: f64,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    let metric = unsafe { &mut *(metric as *mut FakeMetric) };
+    if metric.kind != triton_sys::TRITONSERVER_metrickind_enum_TRITONSERVER_METRIC_KIND_GAUGE {
+        return invalid_arg("Only gauges can be set");
+    }
+    metric.value = value;
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ModelState(
+    model: *mut triton_sys::TRITONBACKEND_Model,
+    state: *mut *mut c_void,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { *state = (*(model as *mut FakeModel)).state };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ModelSetState(
+    model: *mut triton_sys::TRITONBACKEND_Model,
+    state: *mut c_void,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { (*(model as *mut FakeModel)).state = state };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ModelInstanceState(
+    instance: *mut triton_sys::TRITONBACKEND_ModelInstance,
+    state: *mut *mut c_void,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { *state = (*(instance as *mut FakeModelInstance)).state };
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+extern "C" fn TRITONBACKEND_ModelInstanceSetState(
+    instance: *mut triton_sys::TRITONBACKEND_ModelInstance,
+    state: *mut c_void,
+) -> *mut triton_sys::TRITONSERVER_Error {
+    unsafe { (*(instance as *mut FakeModelInstance)).state = state };
+    std::ptr::null_mut()
+}
@@ -0,0 +1,20 @@
+//! Backend API functions added after r22.12, which bindings.rs is generated
+//! from. Only link against Triton r23.10 or newer with the `cancellation`
+//! feature enabled.
+
+use crate::bindings::*;
+
+extern "C" {
+    #[doc = " Query whether the request is cancelled or not.\n\n If possible the backend should terminate any processing and\n send an error response with cancelled status.\n\n \\param request The inference request.\n \\param is_cancelled Returns true if the request is cancelled otherwise it\n would return false.\n \\return a TRITONSERVER_Error indicating success or failure."]
+    pub fn TRITONBACKEND_RequestIsCancelled(
+        request: *mut TRITONBACKEND_Request,
+        is_cancelled: *mut bool,
+    ) -> *mut TRITONSERVER_Error;
+}
+extern "C" {
+    #[doc = " Query whether the response factory is cancelled or not.\n\n \\param factory The response factory\n \\param is_cancelled Returns true if the request is cancelled otherwise it\n would return false.\n \\return a TRITONSERVER_Error indicating success or failure."]
+    pub fn TRITONBACKEND_ResponseFactoryIsCancelled(
+        factory: *mut TRITONBACKEND_ResponseFactory,
+        is_cancelled: *mut bool,
+    ) -> *mut TRITONSERVER_Error;
+}
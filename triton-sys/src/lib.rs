@@ -5,4 +5,8 @@
 // include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 pub mod bindings;
 pub use bindings::*;
+#[cfg(feature = "cancellation")]
+pub mod cancellation;
+#[cfg(feature = "cancellation")]
+pub use cancellation::*;
 